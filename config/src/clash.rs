@@ -0,0 +1,187 @@
+use crate::rule::{Action, Rule};
+use crate::{Address, Config};
+use serde::Serialize;
+
+const PROXY_GROUP: &str = "PROXY";
+
+#[derive(Serialize)]
+struct ClashConfig {
+    proxies: Vec<ClashProxy>,
+    #[serde(rename = "proxy-groups")]
+    proxy_groups: Vec<ClashProxyGroup>,
+    rules: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ClashProxy {
+    name: String,
+    #[serde(rename = "type")]
+    ty: &'static str,
+    server: String,
+    port: u16,
+    cipher: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+struct ClashProxyGroup {
+    name: &'static str,
+    #[serde(rename = "type")]
+    ty: &'static str,
+    proxies: Vec<String>,
+}
+
+impl Config {
+    /// Export the shadowsocks servers and the rules as a minimal Clash config.
+    ///
+    /// The servers are put in a `PROXY` select group, which `PROXY` rules point to. Clash has
+    /// no probing, `PROBE` rules are exported as `PROXY`. Servers with a plugin are skipped,
+    /// Clash plugins take other options than SIP003 plugins. Without any server the group only
+    /// has `DIRECT`, Clash rejects empty groups.
+    pub fn export_clash_config(&self) -> String {
+        let proxies: Vec<ClashProxy> = self
+            .shadowsocks_servers
+            .iter()
+            .flat_map(|servers| servers.iter())
+            .filter(|server| server.plugin().is_none())
+            .map(|server| {
+                let (host, port) = match server.addr() {
+                    Address::SocketAddress(addr) => (addr.ip().to_string(), addr.port()),
+                    Address::DomainNameAddress(domain, port) => (domain.clone(), *port),
+                };
+                ClashProxy {
+                    name: server.name().to_string(),
+                    ty: "ss",
+                    server: host,
+                    port,
+                    cipher: server.method().to_string(),
+                    password: server.password().to_string(),
+                }
+            })
+            .collect();
+        let mut group_proxies: Vec<String> = proxies.iter().map(|p| p.name.clone()).collect();
+        if group_proxies.is_empty() {
+            group_proxies.push(Action::Direct.to_string());
+        }
+        let clash = ClashConfig {
+            proxy_groups: vec![ClashProxyGroup {
+                name: PROXY_GROUP,
+                ty: "select",
+                proxies: group_proxies,
+            }],
+            proxies,
+            rules: self.rules.rules().iter().map(clash_rule).collect(),
+        };
+        serde_yaml::to_string(&clash).expect("serialize clash config")
+    }
+}
+
+fn clash_rule(rule: &Rule) -> String {
+    format!("{},{}", rule.pattern(), clash_action(rule.action()))
+}
+
+fn clash_action(action: Action) -> String {
+    match action {
+        Action::Proxy | Action::Probe => PROXY_GROUP.to_string(),
+        action => action.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Config;
+    use serde_yaml::Value;
+
+    const CLASH_RULES: &str = "
+  - 'DOMAIN,ad.example.com,REJECT'
+  - 'DOMAIN-SUFFIX,google.com,PROXY'
+  - 'DOMAIN-KEYWORD,baidu,DIRECT'
+  - 'IP-CIDR,10.0.0.0/8,DIRECT'
+  - 'MATCH,PROXY'
+";
+
+    #[test]
+    fn test_export_clash_config() {
+        let seeker_config = format!(
+            "
+dns_start_ip: 11.0.0.10
+dns_servers:
+  - 223.5.5.5:53
+tun_name: utun4
+tun_ip: 11.0.0.1
+tun_cidr: 11.0.0.0/16
+dns_listen: 0.0.0.0:53
+max_connect_errors: 2
+shadowsocks_servers:
+  - name: hk
+    addr: hk.example.com:8388
+    method: aes-256-gcm
+    password: password
+  - name: jp
+    addr: 1.2.3.4:443
+    method: chacha20-ietf-poly1305
+    password: secret
+rules:{}",
+            CLASH_RULES
+        );
+        let config = Config::from_reader(seeker_config.as_bytes()).unwrap();
+        let exported: Value = serde_yaml::from_str(&config.export_clash_config()).unwrap();
+
+        let rules: Value = serde_yaml::from_str(CLASH_RULES).unwrap();
+        assert_eq!(exported["rules"], rules);
+
+        let proxies: Value = serde_yaml::from_str(
+            "
+- name: hk
+  type: ss
+  server: hk.example.com
+  port: 8388
+  cipher: aes-256-gcm
+  password: password
+- name: jp
+  type: ss
+  server: 1.2.3.4
+  port: 443
+  cipher: chacha20-ietf-poly1305
+  password: secret
+",
+        )
+        .unwrap();
+        assert_eq!(exported["proxies"], proxies);
+        assert_eq!(
+            exported["proxy-groups"][0]["proxies"],
+            serde_yaml::from_str::<Value>("[hk, jp]").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_export_clash_config_without_servers() {
+        let seeker_config = "
+dns_start_ip: 11.0.0.10
+dns_servers:
+  - 223.5.5.5:53
+tun_name: utun4
+tun_ip: 11.0.0.1
+tun_cidr: 11.0.0.0/16
+dns_listen: 0.0.0.0:53
+max_connect_errors: 2
+socks5_server:
+  addr: 127.0.0.1:1086
+shadowsocks_servers:
+  - name: hk
+    addr: hk.example.com:8388
+    method: aes-256-gcm
+    password: password
+    plugin: v2ray-plugin
+rules:
+  - 'MATCH,PROXY'
+";
+        let config = Config::from_reader(seeker_config.as_bytes()).unwrap();
+        let exported: Value = serde_yaml::from_str(&config.export_clash_config()).unwrap();
+        assert_eq!(exported["proxies"], Value::Sequence(vec![]));
+        assert_eq!(
+            exported["proxy-groups"][0]["proxies"],
+            serde_yaml::from_str::<Value>("[DIRECT]").unwrap()
+        );
+    }
+}
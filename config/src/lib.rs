@@ -1,22 +1,31 @@
+mod clash;
 pub mod rule;
 mod server_config;
+mod sip002;
 pub use server_config::{ServerAddr, ShadowsocksServerConfig};
+pub use sip002::Sip002UriError;
 pub use socks5_client::Address;
 
 use crate::server_config::ProxyServerConfig;
 use rule::ProxyRules;
 use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
 use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+use std::collections::HashSet;
 use std::fs::File;
 use std::io;
 use std::io::{ErrorKind, Read};
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub shadowsocks_servers: Option<Arc<Vec<ShadowsocksServerConfig>>>,
+    /// SIP002 URIs, appended to `shadowsocks_servers` when the config is loaded
+    #[serde(default)]
+    pub shadowsocks_server_urls: Vec<String>,
     pub socks5_server: Option<ProxyServerConfig>,
     pub http_proxy_server: Option<ProxyServerConfig>,
     pub dns_start_ip: Ipv4Addr,
@@ -32,36 +41,120 @@ pub struct Config {
     pub dns_listen: String,
     #[serde(default)]
     pub gateway_mode: bool,
+    /// Source address of direct connections
+    pub direct_bind_addr: Option<IpAddr>,
+    /// Source address of connections to shadowsocks servers
+    pub proxy_bind_addr: Option<IpAddr>,
     #[serde(with = "duration", default = "default_connect_timeout")]
     pub ping_timeout: Duration,
+    #[serde(with = "duration", default = "default_ping_interval")]
+    pub ping_interval: Duration,
     #[serde(with = "duration", default = "default_connect_timeout")]
     pub dns_timeout: Duration,
+    #[serde(with = "duration", default = "default_dns_negative_ttl")]
+    pub dns_negative_ttl: Duration,
+    /// Warn when a domain resolves to IPs different from its first resolution
+    #[serde(default)]
+    pub dns_change_alert: bool,
     #[serde(with = "duration", default = "default_ping_timeout")]
     pub probe_timeout: Duration,
+    /// Probes allowed per second, 0 means unlimited
+    #[serde(default = "default_max_probes_per_second")]
+    pub max_probes_per_second: u32,
+    /// Retry direct connections refused by the remote through the proxy
+    #[serde(default)]
+    pub retry_on_refuse: bool,
     #[serde(with = "duration", default = "default_connect_timeout")]
     pub connect_timeout: Duration,
     #[serde(with = "duration", default = "default_read_timeout")]
     pub read_timeout: Duration,
     #[serde(with = "duration", default = "default_write_timeout")]
     pub write_timeout: Duration,
+    #[serde(with = "duration", default = "default_udp_session_timeout")]
+    pub udp_session_timeout: Duration,
     pub max_connect_errors: usize,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Remote rule file, replaces `rules` when it can be fetched or read from `rule_cache_path`
+    pub rule_url: Option<String>,
+    #[serde(default = "default_rule_cache_path")]
+    pub rule_cache_path: PathBuf,
+    /// Hex dump the first bytes of each direction of every TCP connection in trace events
+    pub trace_first_bytes: Option<usize>,
+    /// Hex dump all data of every TCP connection to this file
+    pub debug_tcp_log: Option<PathBuf>,
+    #[serde(with = "duration", default = "default_proc_socks_cache_ttl")]
+    pub proc_socks_cache_ttl: Duration,
+    #[serde(default)]
+    pub fail_on_connectivity_error: bool,
+    /// Check GitHub for new releases once a day
+    #[serde(default)]
+    pub check_for_updates: bool,
+    #[serde(with = "ping_urls", default = "default_ping_urls")]
+    pub ping_urls: Vec<(Address, String)>,
+}
+
+/// Output format of the log lines.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable text
+    Text,
+    /// One JSON object per line, for log aggregators
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
 }
 
+fn default_rule_cache_path() -> PathBuf {
+    PathBuf::from("rule_cache.txt")
+}
 fn default_read_timeout() -> Duration {
     Duration::from_secs(30)
 }
 fn default_write_timeout() -> Duration {
     Duration::from_secs(30)
 }
+fn default_udp_session_timeout() -> Duration {
+    Duration::from_secs(30)
+}
 fn default_connect_timeout() -> Duration {
     Duration::from_millis(100)
 }
+fn default_ping_interval() -> Duration {
+    Duration::from_secs(30)
+}
 fn default_ping_timeout() -> Duration {
     Duration::from_secs(3)
 }
+fn default_dns_negative_ttl() -> Duration {
+    Duration::from_secs(15)
+}
+fn default_max_probes_per_second() -> u32 {
+    10
+}
+fn default_proc_socks_cache_ttl() -> Duration {
+    Duration::from_millis(100)
+}
+fn default_ping_urls() -> Vec<(Address, String)> {
+    ["google.com", "twitter.com", "github.com", "youtube.com"]
+        .iter()
+        .map(|host| {
+            (
+                Address::DomainNameAddress(host.to_string(), 80),
+                "/".to_string(),
+            )
+        })
+        .collect()
+}
 
 mod ipv4_cidr {
-    use crate::parse_cidr;
+    use crate::try_parse_cidr;
+    use serde::de::Error;
     use serde::{Deserialize, Deserializer};
     use smoltcp::wire::Ipv4Cidr;
 
@@ -70,7 +163,8 @@ mod ipv4_cidr {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        Ok(parse_cidr(s))
+        try_parse_cidr(&s)
+            .ok_or_else(|| Error::custom(format!("invalid value: {}, expected 10.0.0.0/16", s)))
     }
 }
 
@@ -89,11 +183,16 @@ mod duration {
                 chars.push(c);
             }
         }
-        let n: u64 = num.into_iter().collect::<String>().parse().unwrap();
+        let err = || format!("invalid value: {}, expected 10s or 10ms", s);
+        let n: u64 = num
+            .into_iter()
+            .collect::<String>()
+            .parse()
+            .map_err(|_| err())?;
         match chars.into_iter().collect::<String>().as_str() {
             "s" => Ok(Duration::from_secs(n)),
             "ms" => Ok(Duration::from_millis(n)),
-            _ => Err(format!("invalid value: {}, expected 10s or 10ms", &s)),
+            _ => Err(err()),
         }
     }
 
@@ -106,8 +205,41 @@ mod duration {
     }
 }
 
+mod ping_urls {
+    use crate::Address;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer};
+
+    /// Parse `http://host[:port][/path]` into the address to connect and the path to request.
+    pub fn parse_ping_url(s: &str) -> Result<(Address, String), String> {
+        let rest = s.trim_start_matches("http://");
+        let (host, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        let addr = host.parse().map_err(|e| {
+            format!(
+                "invalid value: {}, {}, expected http://host:port/path",
+                s, e
+            )
+        })?;
+        Ok((addr, path.to_string()))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<(Address, String)>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let urls: Vec<String> = Vec::deserialize(deserializer)?;
+        urls.iter()
+            .map(|s| parse_ping_url(s).map_err(Error::custom))
+            .collect()
+    }
+}
+
 mod rules {
     use crate::rule::{ProxyRules, Rule};
+    use serde::de::Error;
     use serde::{Deserialize, Deserializer};
     use std::str::FromStr;
 
@@ -116,31 +248,115 @@ mod rules {
         D: Deserializer<'de>,
     {
         let rules: Vec<String> = Vec::deserialize(deserializer)?;
-        let rs: Vec<Rule> = rules
+        let rs = rules
             .into_iter()
-            .map(|s| Rule::from_str(&s).unwrap())
-            .collect();
+            .map(|s| Rule::from_str(&s).map_err(Error::custom))
+            .collect::<Result<Vec<_>, _>>()?;
         Ok(ProxyRules::new(rs))
     }
 }
 
-fn parse_cidr(s: String) -> Ipv4Cidr {
+/// Top level keys whose lists are appended instead of replaced when merging configs
+const APPENDED_KEYS: [&str; 3] = ["shadowsocks_servers", "shadowsocks_server_urls", "rules"];
+
+fn merge_config_values(base: &mut Mapping, other: Mapping) {
+    for (key, value) in other {
+        let append = key.as_str().map_or(false, |k| APPENDED_KEYS.contains(&k));
+        let value = match (base.get_mut(&key), value) {
+            (Some(Value::Sequence(base_seq)), Value::Sequence(seq)) if append => {
+                base_seq.extend(seq);
+                continue;
+            }
+            (_, value) => value,
+        };
+        base.insert(key, value);
+    }
+}
+
+fn try_parse_cidr(s: &str) -> Option<Ipv4Cidr> {
     let segments = s.splitn(2, '/').collect::<Vec<&str>>();
-    let addr = segments[0];
-    let len = segments[1];
-    let addr: Ipv4Addr = addr.parse().unwrap();
-    let prefix = len.parse().unwrap();
-    Ipv4Cidr::new(Ipv4Address::from(addr), prefix)
+    let addr: Ipv4Addr = segments[0].parse().ok()?;
+    let prefix: u8 = segments.get(1)?.parse().ok().filter(|p| *p <= 32)?;
+    Some(Ipv4Cidr::new(Ipv4Address::from(addr), prefix))
 }
 
 impl Config {
     pub fn from_config_file(path: &str) -> io::Result<Self> {
-        let file = File::open(&path).unwrap();
-        Config::from_reader(file)
+        Config::from_config_files(&[path])
+    }
+
+    /// Load config files in order, see `from_readers`.
+    pub fn from_config_files(paths: &[&str]) -> io::Result<Self> {
+        let files = paths
+            .iter()
+            .map(File::open)
+            .collect::<io::Result<Vec<_>>>()?;
+        Config::from_readers(files)
+    }
+
+    /// Merge the configs of `readers`, which may each be partial. Later configs override
+    /// earlier ones, except `shadowsocks_servers`, `shadowsocks_server_urls` and `rules`,
+    /// which are appended.
+    pub fn from_readers<R: Read>(readers: impl IntoIterator<Item = R>) -> io::Result<Self> {
+        let mut merged = Mapping::new();
+        for reader in readers {
+            let other: Mapping = serde_yaml::from_reader(reader)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            merge_config_values(&mut merged, other);
+        }
+        let conf: Config = serde_yaml::from_value(Value::Mapping(merged))
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        Config::post_process(conf)
     }
 
     pub fn from_reader<R: Read>(reader: R) -> io::Result<Self> {
-        let conf: Config = serde_yaml::from_reader(reader).expect("serde yaml deserialize error");
+        Config::from_readers(Some(reader))
+    }
+
+    /// Add the servers of `shadowsocks_server_urls`, name the servers and check that a proxy
+    /// is configured.
+    fn post_process(mut conf: Config) -> io::Result<Self> {
+        if conf.shadowsocks_servers.is_some() || !conf.shadowsocks_server_urls.is_empty() {
+            let mut servers = conf
+                .shadowsocks_servers
+                .as_deref()
+                .cloned()
+                .unwrap_or_default();
+            for (i, url) in conf.shadowsocks_server_urls.iter().enumerate() {
+                let server = ShadowsocksServerConfig::from_sip002_uri(url).map_err(|e| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("invalid shadowsocks_server_urls[{}]: {}", i, e),
+                    )
+                })?;
+                servers.push(server);
+            }
+            // Names identify servers in logs, make sure they are set and unique
+            let mut names = HashSet::new();
+            for (i, server) in servers.iter_mut().enumerate() {
+                if server.name().is_empty() {
+                    server.set_name(format!("server-{}", i));
+                }
+                if !names.insert(server.name().to_string()) {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("duplicate shadowsocks server name {}", server.name()),
+                    ));
+                }
+                // The plugin is the peer of the TCP connection, the header would carry the
+                // plugin's local address as destination.
+                if server.plugin().is_some() && server.proxy_protocol() {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "shadowsocks server {}: proxy_protocol can not be used with plugin",
+                            server.name()
+                        ),
+                    ));
+                }
+            }
+            conf.shadowsocks_servers = Some(Arc::new(servers));
+        }
         if let (None, None, None) = (
             &conf.shadowsocks_servers,
             &conf.socks5_server,
@@ -158,11 +374,160 @@ impl Config {
 #[cfg(test)]
 mod tests {
     use super::duration::parse_duration;
+    use super::ping_urls::parse_ping_url;
+    use crate::{Address, Config};
     use std::time::Duration;
 
+    const CONFIG: &str = "
+dns_start_ip: 11.0.0.10
+dns_servers:
+  - 223.5.5.5:53
+tun_name: utun4
+tun_ip: 11.0.0.1
+tun_cidr: 11.0.0.0/16
+dns_listen: 0.0.0.0:53
+max_connect_errors: 2
+rules:
+  - 'MATCH,PROXY'
+";
+
     #[test]
     fn test_parse_duration() {
         assert_eq!(parse_duration("10s"), Ok(Duration::from_secs(10)));
         assert_eq!(parse_duration("8ms"), Ok(Duration::from_millis(8)));
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("10m").is_err());
+    }
+
+    #[test]
+    fn test_invalid_rule() {
+        let config = format!(
+            "{}
+socks5_server:
+  addr: 127.0.0.1:1086",
+            CONFIG
+        );
+        let rules = "rules:\n  - 'DOMAIN,example.com,PROXYY'";
+        let err = Config::from_readers(vec![config.as_bytes(), rules.as_bytes()]).unwrap_err();
+        assert!(err.to_string().contains("invalid rule"));
+        assert!(Config::from_readers(vec![config.as_bytes()]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_ping_url() {
+        assert_eq!(
+            parse_ping_url("http://google.com/generate_204"),
+            Ok((
+                Address::DomainNameAddress("google.com".to_string(), 80),
+                "/generate_204".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_ping_url("github.com:8080"),
+            Ok((
+                Address::DomainNameAddress("github.com".to_string(), 8080),
+                "/".to_string()
+            ))
+        );
+        assert!(parse_ping_url("http://:80/").is_err());
+    }
+
+    #[test]
+    fn test_default_server_name() {
+        let config = format!(
+            "{}
+shadowsocks_servers:
+  - addr: 1.2.3.4:443
+    method: aes-256-gcm
+    password: password
+  - name: hk
+    addr: hk.example.com:8388
+    method: aes-256-gcm
+    password: password
+shadowsocks_server_urls:
+  - ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888
+  - ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwYTpzcw@192.168.100.1:8888",
+            CONFIG
+        );
+        let config = Config::from_reader(config.as_bytes()).unwrap();
+        let names: Vec<_> = config
+            .shadowsocks_servers
+            .unwrap()
+            .iter()
+            .map(|s| s.name().to_string())
+            .collect();
+        assert_eq!(names, ["server-0", "hk", "server-2", "server-3"]);
+    }
+
+    #[test]
+    fn test_duplicate_server_name() {
+        let config = format!(
+            "{}
+shadowsocks_servers:
+  - name: server-1
+    addr: 1.2.3.4:443
+    method: aes-256-gcm
+    password: password
+  - addr: hk.example.com:8388
+    method: aes-256-gcm
+    password: password",
+            CONFIG
+        );
+        assert!(Config::from_reader(config.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_plugin_with_proxy_protocol() {
+        let config = format!(
+            "{}
+shadowsocks_servers:
+  - addr: hk.example.com:8388
+    method: aes-256-gcm
+    password: password
+    plugin: v2ray-plugin
+    proxy_protocol: true",
+            CONFIG
+        );
+        assert!(Config::from_reader(config.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_merge_configs() {
+        let base = format!(
+            "{}
+shadowsocks_servers:
+  - name: hk
+    addr: hk.example.com:8388
+    method: aes-256-gcm
+    password: password",
+            CONFIG
+        );
+        let overrides = "
+connect_timeout: 5s
+shadowsocks_servers:
+  - name: jp
+    addr: 1.2.3.4:443
+    method: aes-256-gcm
+    password: password
+rules:
+  - 'DOMAIN,ad.example.com,REJECT'
+";
+        let config = Config::from_readers(vec![base.as_bytes(), overrides.as_bytes()]).unwrap();
+        let names: Vec<_> = config
+            .shadowsocks_servers
+            .unwrap()
+            .iter()
+            .map(|s| s.name().to_string())
+            .collect();
+        assert_eq!(names, ["hk", "jp"]);
+        assert_eq!(config.connect_timeout, Duration::from_secs(5));
+        assert_eq!(config.tun_name, "utun4");
+        assert_eq!(config.rules.rules().len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_yaml() {
+        assert!(Config::from_reader("tun_name: [utun4".as_bytes()).is_err());
+        assert!(Config::from_reader(CONFIG.replace("11.0.0.1", "x").as_bytes()).is_err());
     }
 }
@@ -1,6 +1,7 @@
-use crate::parse_cidr;
+use crate::try_parse_cidr;
 use serde::export::Formatter;
 use smoltcp::wire::Ipv4Cidr;
+use std::error;
 use std::fmt;
 use std::net::Ipv4Addr;
 use std::str::FromStr;
@@ -23,32 +24,135 @@ pub enum Action {
     Probe,
 }
 
+/// Result of `ProxyRules::explain`. `action` is the default action if no rule matched.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RuleExplanation {
+    pub matched_rule: Option<usize>,
+    pub rule_pattern: Option<String>,
+    pub action: Action,
+    pub steps: Vec<EvalStep>,
+}
+
+/// One rule considered by `ProxyRules::explain`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EvalStep {
+    pub rule_index: usize,
+    pub rule_pattern: String,
+    pub matched: bool,
+}
+
+impl Rule {
+    /// Rule type and criteria without the action, eg. `DOMAIN-SUFFIX,google.com`.
+    pub fn pattern(&self) -> String {
+        match self {
+            Rule::Domain(d, _) => format!("DOMAIN,{}", d),
+            Rule::DomainSuffix(d, _) => format!("DOMAIN-SUFFIX,{}", d),
+            Rule::DomainKeyword(d, _) => format!("DOMAIN-KEYWORD,{}", d),
+            Rule::IpCidr(cidr, _) => format!("IP-CIDR,{}", cidr),
+            Rule::Match(_) => "MATCH".to_string(),
+        }
+    }
+
+    pub fn action(&self) -> Action {
+        match self {
+            Rule::Domain(_, action)
+            | Rule::DomainSuffix(_, action)
+            | Rule::DomainKeyword(_, action)
+            | Rule::IpCidr(_, action)
+            | Rule::Match(action) => *action,
+        }
+    }
+
+    fn action_for_domain(&self, domain: &str) -> Option<Action> {
+        match self {
+            Rule::Domain(d, action) if d == domain => Some(*action),
+            Rule::DomainSuffix(d, action) if domain.ends_with(d) => Some(*action),
+            Rule::DomainKeyword(d, action) if domain.contains(d) => Some(*action),
+            Rule::Match(action) => Some(*action),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ProxyRules {
     rules: Arc<Vec<Rule>>,
+    domain_count: usize,
+    cidr_count: usize,
 }
 
 impl ProxyRules {
     pub fn new(rules: Vec<Rule>) -> Self {
+        let domain_count = rules
+            .iter()
+            .filter(|rule| {
+                matches!(
+                    rule,
+                    Rule::Domain(..) | Rule::DomainSuffix(..) | Rule::DomainKeyword(..)
+                )
+            })
+            .count();
+        let cidr_count = rules
+            .iter()
+            .filter(|rule| matches!(rule, Rule::IpCidr(..)))
+            .count();
         Self {
             rules: Arc::new(rules),
+            domain_count,
+            cidr_count,
         }
     }
 
+    /// Number of `DOMAIN`, `DOMAIN-SUFFIX` and `DOMAIN-KEYWORD` rules
+    pub fn domain_count(&self) -> usize {
+        self.domain_count
+    }
+
+    /// Number of `IP-CIDR` rules
+    pub fn cidr_count(&self) -> usize {
+        self.cidr_count
+    }
+
+    pub fn total_rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
     pub fn action_for_domain(&self, domain: &str) -> Option<Action> {
         self.rules
             .iter()
-            .filter_map(|rule| match rule {
-                Rule::Domain(d, action) if d == domain => Some(*action),
-                Rule::DomainSuffix(d, action) if domain.ends_with(d) => Some(*action),
-                Rule::DomainKeyword(d, action) if domain.contains(d) => Some(*action),
-                Rule::Match(action) => Some(*action),
-                _ => None,
-            })
+            .filter_map(|rule| rule.action_for_domain(domain))
             .take(1)
             .next()
     }
 
+    /// Evaluate the rules for `domain` like `action_for_domain`, recording every rule
+    /// considered until the first match.
+    pub fn explain(&self, domain: &str) -> RuleExplanation {
+        let mut steps = vec![];
+        for (index, rule) in self.rules.iter().enumerate() {
+            let action = rule.action_for_domain(domain);
+            steps.push(EvalStep {
+                rule_index: index,
+                rule_pattern: rule.pattern(),
+                matched: action.is_some(),
+            });
+            if let Some(action) = action {
+                return RuleExplanation {
+                    matched_rule: Some(index),
+                    rule_pattern: Some(rule.pattern()),
+                    action,
+                    steps,
+                };
+            }
+        }
+        RuleExplanation {
+            matched_rule: None,
+            rule_pattern: None,
+            action: self.default_action(),
+            steps,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn action_for_ip(&self, ip: Ipv4Addr) -> Option<Action> {
         self.rules
@@ -61,13 +165,53 @@ impl ProxyRules {
             .next()
     }
 
+    /// Parse a rule file, one rule per line. Empty lines and lines starting with `#` are
+    /// skipped.
+    pub fn from_rule_file(content: &str) -> Result<Self, ParseRuleError> {
+        let rules = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Rule::from_str)
+            .collect::<Result<_, _>>()?;
+        Ok(ProxyRules::new(rules))
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
     pub fn default_action(&self) -> Action {
         Action::Direct
     }
 }
 
+/// Rule that is not `TYPE,criteria,ACTION` or `MATCH,ACTION` with a known type and action
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseRuleError(pub String);
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid rule {}", self.0)
+    }
+}
+
+impl error::Error for ParseRuleError {}
+
+/// Unknown action keyword in a rule
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseActionError(pub String);
+
+impl fmt::Display for ParseActionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown action {}", self.0)
+    }
+}
+
+impl error::Error for ParseActionError {}
+
 impl FromStr for Action {
-    type Err = ();
+    type Err = ParseActionError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
@@ -75,42 +219,175 @@ impl FromStr for Action {
             "DIRECT" => Action::Direct,
             "PROXY" => Action::Proxy,
             "PROBE" => Action::Probe,
-            _ => unreachable!(),
+            _ => return Err(ParseActionError(s.to_string())),
         })
     }
 }
 
+/// The keyword used in rules, eg. `PROXY`.
 impl fmt::Display for Action {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        let keyword = match self {
+            Action::Reject => "REJECT",
+            Action::Direct => "DIRECT",
+            Action::Proxy => "PROXY",
+            Action::Probe => "PROBE",
+        };
+        write!(f, "{}", keyword)
+    }
+}
+
+/// The rule as written in the config, eg. `DOMAIN-SUFFIX,google.com,PROXY`.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.pattern(), self.action())
     }
 }
 
 impl FromStr for Rule {
-    type Err = ();
+    type Err = ParseRuleError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseRuleError(s.to_string());
         let segments = s.splitn(3, ',').collect::<Vec<_>>();
         let (rule, criteria, action) = match segments.len() {
             2 => (segments[0], "", segments[1]),
             3 => (segments[0], segments[1], segments[2]),
-            _ => unreachable!(),
+            _ => return Err(err()),
         };
+        let action = Action::from_str(action).map_err(|_| err())?;
 
         Ok(match rule {
-            "DOMAIN" => Rule::Domain(criteria.to_string(), Action::from_str(action).unwrap()),
-            "DOMAIN-SUFFIX" => {
-                Rule::DomainSuffix(criteria.to_string(), Action::from_str(action).unwrap())
-            }
-            "DOMAIN-KEYWORD" => {
-                Rule::DomainKeyword(criteria.to_string(), Action::from_str(action).unwrap())
-            }
-            "IP-CIDR" => Rule::IpCidr(
-                parse_cidr(criteria.to_string()),
-                Action::from_str(action).unwrap(),
-            ),
-            "MATCH" => Rule::Match(Action::from_str(action).unwrap()),
-            _ => unreachable!(),
+            "DOMAIN" => Rule::Domain(criteria.to_string(), action),
+            "DOMAIN-SUFFIX" => Rule::DomainSuffix(criteria.to_string(), action),
+            "DOMAIN-KEYWORD" => Rule::DomainKeyword(criteria.to_string(), action),
+            "IP-CIDR" => Rule::IpCidr(try_parse_cidr(criteria).ok_or_else(err)?, action),
+            "MATCH" => Rule::Match(action),
+            _ => return Err(err()),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> ProxyRules {
+        ProxyRules::new(
+            [
+                "DOMAIN,ad.example.com,REJECT",
+                "DOMAIN-SUFFIX,google.com,PROXY",
+                "IP-CIDR,10.0.0.0/8,DIRECT",
+                "DOMAIN-KEYWORD,baidu,DIRECT",
+            ]
+            .iter()
+            .map(|r| Rule::from_str(r).unwrap())
+            .collect(),
+        )
+    }
+
+    #[test]
+    fn test_action_from_str() {
+        for action in [Action::Reject, Action::Direct, Action::Proxy, Action::Probe].iter() {
+            assert_eq!(Action::from_str(&action.to_string()), Ok(*action));
+        }
+        assert_eq!(Action::Proxy.to_string(), "PROXY");
+        assert_eq!(
+            Action::from_str("proxy"),
+            Err(ParseActionError("proxy".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_rule_round_trip() {
+        for rule in [
+            "DOMAIN,ad.example.com,REJECT",
+            "DOMAIN-SUFFIX,google.com,PROXY",
+            "DOMAIN-KEYWORD,baidu,DIRECT",
+            "IP-CIDR,10.0.0.0/8,DIRECT",
+            "MATCH,PROBE",
+        ]
+        .iter()
+        {
+            assert_eq!(&Rule::from_str(rule).unwrap().to_string(), rule);
+        }
+    }
+
+    #[test]
+    fn test_from_rule_file() {
+        let rules = ProxyRules::from_rule_file(
+            "# comment
+DOMAIN-SUFFIX,google.com,PROXY
+
+  MATCH,DIRECT
+",
+        )
+        .unwrap();
+        assert_eq!(
+            rules.rules(),
+            &[
+                Rule::DomainSuffix("google.com".to_string(), Action::Proxy),
+                Rule::Match(Action::Direct)
+            ]
+        );
+
+        for rule in [
+            "DOMAIN,google.com",
+            "URL,google.com,PROXY",
+            "IP-CIDR,10.0.0.0/33,DIRECT",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                ProxyRules::from_rule_file(rule).err(),
+                Some(ParseRuleError(rule.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_rule_counts() {
+        let rules = rules();
+        assert_eq!(rules.domain_count(), 3);
+        assert_eq!(rules.cidr_count(), 1);
+        assert_eq!(rules.total_rule_count(), 4);
+    }
+
+    #[test]
+    fn test_explain() {
+        let explanation = rules().explain("www.google.com");
+        assert_eq!(explanation.matched_rule, Some(1));
+        assert_eq!(
+            explanation.rule_pattern.as_deref(),
+            Some("DOMAIN-SUFFIX,google.com")
+        );
+        assert_eq!(explanation.action, Action::Proxy);
+        assert_eq!(
+            explanation.steps,
+            vec![
+                EvalStep {
+                    rule_index: 0,
+                    rule_pattern: "DOMAIN,ad.example.com".to_string(),
+                    matched: false,
+                },
+                EvalStep {
+                    rule_index: 1,
+                    rule_pattern: "DOMAIN-SUFFIX,google.com".to_string(),
+                    matched: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explain_no_match() {
+        let rules = rules();
+        let explanation = rules.explain("example.com");
+        assert_eq!(explanation.matched_rule, None);
+        assert_eq!(explanation.rule_pattern, None);
+        assert_eq!(explanation.action, rules.default_action());
+        assert_eq!(explanation.steps.len(), 4);
+        assert!(explanation.steps.iter().all(|s| !s.matched));
+        assert_eq!(explanation.steps[2].rule_pattern, "IP-CIDR,10.0.0.0/8");
+    }
+}
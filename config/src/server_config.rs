@@ -90,7 +90,8 @@ pub struct ProxyServerConfig {
 /// Configuration for a server
 #[derive(Clone, Debug, Deserialize)]
 pub struct ShadowsocksServerConfig {
-    /// Server name
+    /// Server name, `server-{index}` if not configured
+    #[serde(default)]
     name: String,
     /// Server address
     #[serde(with = "server_addr")]
@@ -100,6 +101,18 @@ pub struct ShadowsocksServerConfig {
     /// Encryption type (method)
     #[serde(with = "cipher_type")]
     method: CipherType,
+    /// SIP003 plugin executable
+    #[serde(default)]
+    plugin: Option<String>,
+    /// Options passed to the plugin in `SS_PLUGIN_OPTIONS`
+    #[serde(default)]
+    plugin_opts: Option<String>,
+    /// Send a PROXY protocol v2 header before any shadowsocks data
+    #[serde(default)]
+    proxy_protocol: bool,
+    /// Local address the running plugin listens on
+    #[serde(skip)]
+    plugin_addr: Option<Address>,
 }
 
 mod cipher_type {
@@ -128,8 +141,12 @@ mod server_addr {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        Address::from_str(&s)
-            .map_err(|_| Error::custom(format!("invalid value: {}, ip:port or domain:port", s)))
+        Address::from_str(&s).map_err(|e| {
+            Error::custom(format!(
+                "invalid value: {}, {}, expected ip:port or domain:port",
+                s, e
+            ))
+        })
     }
 }
 
@@ -147,6 +164,10 @@ impl ShadowsocksServerConfig {
             addr,
             password: pwd,
             method,
+            plugin: None,
+            plugin_opts: None,
+            proxy_protocol: false,
+            plugin_addr: None,
         }
     }
 
@@ -175,6 +196,11 @@ impl ShadowsocksServerConfig {
         &self.name
     }
 
+    /// Set server name
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
     /// Set server addr
     pub fn set_addr(&mut self, a: Address) {
         self.addr = a;
@@ -185,6 +211,43 @@ impl ShadowsocksServerConfig {
         &self.addr
     }
 
+    /// Get the address TCP connections are made to, the plugin's local address if a plugin
+    /// is running, otherwise the server address
+    pub fn tcp_addr(&self) -> &Address {
+        self.plugin_addr.as_ref().unwrap_or(&self.addr)
+    }
+
+    /// Set plugin and its options
+    pub fn set_plugin(&mut self, plugin: String, opts: Option<String>) {
+        self.plugin = Some(plugin);
+        self.plugin_opts = opts;
+    }
+
+    /// Get plugin
+    pub fn plugin(&self) -> Option<&str> {
+        self.plugin.as_deref()
+    }
+
+    /// Get plugin options
+    pub fn plugin_opts(&self) -> Option<&str> {
+        self.plugin_opts.as_deref()
+    }
+
+    /// Set whether to send a PROXY protocol v2 header
+    pub fn set_proxy_protocol(&mut self, enabled: bool) {
+        self.proxy_protocol = enabled;
+    }
+
+    /// Whether to send a PROXY protocol v2 header
+    pub fn proxy_protocol(&self) -> bool {
+        self.proxy_protocol
+    }
+
+    /// Set the local address of the running plugin
+    pub fn set_plugin_addr(&mut self, a: Address) {
+        self.plugin_addr = Some(a);
+    }
+
     /// Get encryption key
     pub fn key(&self) -> Bytes {
         self.method.bytes_to_key(self.password.as_bytes())
@@ -0,0 +1,267 @@
+//! SIP002 URIs, `ss://BASE64URL(method:password)@host:port/?plugin=name%3Bopts#tag`.
+//!
+//! See https://shadowsocks.org/en/wiki/SIP002-URI-Scheme.html
+use crate::{Address, ShadowsocksServerConfig};
+use crypto::CipherType;
+use socks5_client::AddressParseError;
+use std::error;
+use std::fmt::{self, Formatter};
+use std::str::FromStr;
+
+/// Parse SIP002 URI error
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Sip002UriError {
+    InvalidScheme,
+    InvalidUserInfo,
+    UnknownMethod(String),
+    InvalidAddress(AddressParseError),
+    InvalidPercentEncoding(String),
+}
+
+impl fmt::Display for Sip002UriError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Sip002UriError::InvalidScheme => write!(f, "uri should start with ss://"),
+            Sip002UriError::InvalidUserInfo => {
+                write!(f, "invalid userinfo, expected BASE64URL(method:password)")
+            }
+            Sip002UriError::UnknownMethod(m) => write!(f, "unknown method {}", m),
+            Sip002UriError::InvalidAddress(e) => write!(f, "invalid address, {}", e),
+            Sip002UriError::InvalidPercentEncoding(s) => {
+                write!(f, "invalid percent encoding {}", s)
+            }
+        }
+    }
+}
+
+impl error::Error for Sip002UriError {}
+
+impl ShadowsocksServerConfig {
+    /// Parse a SIP002 URI. The `#tag` fragment is used as server name, the name is left empty
+    /// if missing so that the config names the server by its index.
+    ///
+    /// The legacy `ss://BASE64(method:password@host:port)#tag` form is accepted too.
+    pub fn from_sip002_uri(uri: &str) -> Result<ShadowsocksServerConfig, Sip002UriError> {
+        let rest = uri
+            .strip_prefix("ss://")
+            .ok_or(Sip002UriError::InvalidScheme)?;
+        let (rest, tag) = split_once(rest, '#');
+        let (rest, query) = split_once(rest, '?');
+        let rest = rest.trim_end_matches('/');
+
+        let (method, password, host) = match rest.rfind('@') {
+            Some(pos) => {
+                let (method, password) = parse_user_info(&rest[..pos])?;
+                (method, password, rest[pos + 1..].to_string())
+            }
+            None => {
+                let decoded = decode_base64(rest)?;
+                let pos = decoded.rfind('@').ok_or(Sip002UriError::InvalidUserInfo)?;
+                let (method, password) = split_method_password(&decoded[..pos])?;
+                (method, password, decoded[pos + 1..].to_string())
+            }
+        };
+
+        let addr = Address::from_str(&host).map_err(Sip002UriError::InvalidAddress)?;
+        let method =
+            CipherType::from_str(&method).map_err(|_| Sip002UriError::UnknownMethod(method))?;
+        let name = match tag {
+            Some(tag) => percent_decode(tag)?,
+            None => String::new(),
+        };
+        let mut config = ShadowsocksServerConfig::new(name, addr, password, method);
+
+        for param in query.unwrap_or("").split('&') {
+            if let Some(plugin) = param.strip_prefix("plugin=") {
+                let plugin = percent_decode(plugin)?;
+                let (plugin, opts) = split_once(&plugin, ';');
+                config.set_plugin(plugin.to_string(), opts.map(ToString::to_string));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Format the config as a SIP002 URI, the reverse of `from_sip002_uri`.
+    pub fn to_sip002_uri(&self) -> String {
+        let user_info = base64::encode_config(
+            format!("{}:{}", self.method(), self.password()),
+            base64::URL_SAFE_NO_PAD,
+        );
+        let mut uri = format!("ss://{}@{}", user_info, self.addr());
+        if let Some(plugin) = self.plugin() {
+            let plugin = match self.plugin_opts() {
+                Some(opts) => format!("{};{}", plugin, opts),
+                None => plugin.to_string(),
+            };
+            uri.push_str("/?plugin=");
+            uri.push_str(&percent_encode(&plugin));
+        }
+        if !self.name().is_empty() {
+            uri.push('#');
+            uri.push_str(&percent_encode(self.name()));
+        }
+        uri
+    }
+}
+
+fn split_once(s: &str, delimiter: char) -> (&str, Option<&str>) {
+    match s.find(delimiter) {
+        Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+        None => (s, None),
+    }
+}
+
+/// Userinfo is `BASE64URL(method:password)`, or plain `method:password` with the password
+/// percent encoded.
+fn parse_user_info(user_info: &str) -> Result<(String, String), Sip002UriError> {
+    if user_info.contains(':') {
+        let decoded = percent_decode(user_info)?;
+        return split_method_password(&decoded);
+    }
+    split_method_password(&decode_base64(user_info)?)
+}
+
+fn split_method_password(s: &str) -> Result<(String, String), Sip002UriError> {
+    match split_once(s, ':') {
+        (method, Some(password)) if !method.is_empty() => {
+            Ok((method.to_string(), password.to_string()))
+        }
+        _ => Err(Sip002UriError::InvalidUserInfo),
+    }
+}
+
+fn decode_base64(s: &str) -> Result<String, Sip002UriError> {
+    let s = s.trim_end_matches('=');
+    let bytes = base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+        .or_else(|_| base64::decode_config(s, base64::STANDARD_NO_PAD))
+        .map_err(|_| Sip002UriError::InvalidUserInfo)?;
+    String::from_utf8(bytes).map_err(|_| Sip002UriError::InvalidUserInfo)
+}
+
+fn percent_decode(s: &str) -> Result<String, Sip002UriError> {
+    let err = || Sip002UriError::InvalidPercentEncoding(s.to_string());
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3).ok_or_else(err)?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| err())?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| err())
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_sip002_uri() {
+        let config = ShadowsocksServerConfig::from_sip002_uri(
+            "ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888#Example1",
+        )
+        .unwrap();
+        assert_eq!(config.name(), "Example1");
+        assert_eq!(
+            config.addr(),
+            &"192.168.100.1:8888".parse::<Address>().unwrap()
+        );
+        assert_eq!(config.method().to_string(), "aes-128-gcm");
+        assert_eq!(config.password(), "test");
+        assert_eq!(config.plugin(), None);
+
+        let config = ShadowsocksServerConfig::from_sip002_uri(
+            "ss://cmM0LW1kNTpwYXNzd2Q@example.com:8888/?plugin=obfs-local%3Bobfs%3Dhttp#Example%202",
+        )
+        .unwrap();
+        assert_eq!(config.name(), "Example 2");
+        assert_eq!(
+            config.addr(),
+            &Address::DomainNameAddress("example.com".to_string(), 8888)
+        );
+        assert_eq!(config.method().to_string(), "rc4-md5");
+        assert_eq!(config.password(), "passwd");
+        assert_eq!(config.plugin(), Some("obfs-local"));
+        assert_eq!(config.plugin_opts(), Some("obfs=http"));
+
+        // legacy form, without name
+        let config = ShadowsocksServerConfig::from_sip002_uri(
+            "ss://YWVzLTI1Ni1nY206cGFzc3dvcmRAZXhhbXBsZS5jb206ODM4OA==",
+        )
+        .unwrap();
+        assert_eq!(config.name(), "");
+        assert_eq!(config.method().to_string(), "aes-256-gcm");
+        assert_eq!(config.password(), "password");
+
+        // plain userinfo
+        let config =
+            ShadowsocksServerConfig::from_sip002_uri("ss://aes-256-gcm:pa%40ss@[::1]:8388")
+                .unwrap();
+        assert_eq!(config.password(), "pa@ss");
+        assert_eq!(config.addr(), &"[::1]:8388".parse::<Address>().unwrap());
+    }
+
+    #[test]
+    fn test_sip002_uri_round_trip() {
+        let uris = [
+            "ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwYTpzcw@example.com:443#server%201",
+            "ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888/?plugin=v2ray-plugin%3Btls%3Bhost%3Dexample.com#hk",
+            "ss://YWVzLTEyOC1nY206dGVzdA@[2001:db8::1]:8888#v6",
+            "ss://YWVzLTEyOC1nY206dGVzdA@example.com:443",
+        ];
+        for uri in uris.iter() {
+            let config = ShadowsocksServerConfig::from_sip002_uri(uri).unwrap();
+            assert_eq!(&config.to_sip002_uri(), uri);
+        }
+    }
+
+    #[test]
+    fn test_from_sip002_uri_error() {
+        let parse = |uri| ShadowsocksServerConfig::from_sip002_uri(uri).err();
+        assert_eq!(
+            parse("http://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888"),
+            Some(Sip002UriError::InvalidScheme)
+        );
+        assert_eq!(
+            parse("ss://!!!@192.168.100.1:8888"),
+            Some(Sip002UriError::InvalidUserInfo)
+        );
+        // base64 of "test", no method
+        assert_eq!(
+            parse("ss://dGVzdA@192.168.100.1:8888"),
+            Some(Sip002UriError::InvalidUserInfo)
+        );
+        assert_eq!(
+            parse("ss://unknown:test@192.168.100.1:8888"),
+            Some(Sip002UriError::UnknownMethod("unknown".to_string()))
+        );
+        assert_eq!(
+            parse("ss://YWVzLTEyOC1nY206dGVzdA@:8888"),
+            Some(Sip002UriError::InvalidAddress(
+                AddressParseError::EmptyHostname
+            ))
+        );
+        assert_eq!(
+            parse("ss://YWVzLTEyOC1nY206dGVzdA@example.com:8888#%zz"),
+            Some(Sip002UriError::InvalidPercentEncoding("%zz".to_string()))
+        );
+    }
+}
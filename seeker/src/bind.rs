@@ -0,0 +1,127 @@
+use async_std::io::timeout;
+use async_std::net::{TcpStream, UdpSocket as AsyncUdpSocket};
+use async_std::task::spawn_blocking;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// Connect to `addr` within `dur`, with `bind_addr` as source address if set. `bind_addr` is
+/// ignored if it is not of the same family as `addr`.
+pub async fn connect_tcp(
+    bind_addr: Option<IpAddr>,
+    addr: SocketAddr,
+    dur: Duration,
+) -> Result<TcpStream> {
+    let bind_addr = match bind_addr {
+        Some(a) if a.is_ipv4() == addr.is_ipv4() => a,
+        _ => return timeout(dur, TcpStream::connect(addr)).await,
+    };
+    // async-std can not bind a socket before connecting, connect a socket2 socket on the
+    // blocking pool instead. The connect has its own timeout, a timeout around the blocking
+    // task would not stop the thread.
+    let stream = spawn_blocking(move || {
+        let domain = if addr.is_ipv4() {
+            Domain::ipv4()
+        } else {
+            Domain::ipv6()
+        };
+        let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+        socket.bind(&SocketAddr::new(bind_addr, 0).into())?;
+        socket.connect_timeout(&addr.into(), dur)?;
+        Ok::<_, Error>(socket.into_tcp_stream())
+    })
+    .await?;
+    Ok(TcpStream::from(stream))
+}
+
+/// Bind a UDP socket to send to `addr`, on `bind_addr` if set. Like `connect_tcp`, `bind_addr`
+/// is ignored if it is not of the same family as `addr`.
+pub async fn bind_udp(bind_addr: Option<IpAddr>, addr: SocketAddr) -> Result<AsyncUdpSocket> {
+    let bind_addr = match bind_addr {
+        Some(a) if a.is_ipv4() == addr.is_ipv4() => a,
+        _ if addr.is_ipv4() => Ipv4Addr::UNSPECIFIED.into(),
+        _ => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    AsyncUdpSocket::bind((bind_addr, 0)).await
+}
+
+/// Attempts a TCP connect to `addr` and returns true if it succeeds within `dur`.
+pub async fn check_port_reachability(
+    bind_addr: Option<IpAddr>,
+    addr: SocketAddr,
+    dur: Duration,
+) -> bool {
+    connect_tcp(bind_addr, addr, dur).await.is_ok()
+}
+
+/// Check that `addr` is an address of this host, so that it can be bound.
+pub fn check_bind_addr(name: &str, addr: IpAddr) -> Result<()> {
+    UdpSocket::bind((addr, 0)).map(|_| ()).map_err(|e| {
+        Error::new(
+            ErrorKind::AddrNotAvailable,
+            format!("{} {} is not an address of this host: {}", name, addr, e),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task::block_on;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_check_port_reachability() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dur = Duration::from_secs(1);
+        assert!(block_on(check_port_reachability(None, addr, dur)));
+        assert!(block_on(check_port_reachability(
+            Some("127.0.0.1".parse().unwrap()),
+            addr,
+            dur
+        )));
+    }
+
+    #[test]
+    fn test_check_port_reachability_closed_port() {
+        // A bound socket that doesn't listen keeps the port closed, no other socket can listen
+        // on it during the check.
+        let socket = Socket::new(Domain::ipv4(), Type::stream(), Some(Protocol::tcp())).unwrap();
+        socket
+            .bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into())
+            .unwrap();
+        let addr: SocketAddr = socket.local_addr().unwrap().as_inet().unwrap().into();
+        let dur = Duration::from_secs(1);
+        assert!(!block_on(check_port_reachability(None, addr, dur)));
+        assert!(!block_on(check_port_reachability(
+            Some("127.0.0.1".parse().unwrap()),
+            addr,
+            dur
+        )));
+    }
+
+    #[test]
+    fn test_bind_udp() {
+        let v4_addr: SocketAddr = "1.2.3.4:53".parse().unwrap();
+        let v6_addr: SocketAddr = "[2001:db8::1]:53".parse().unwrap();
+        let local_ip = |bind_addr: Option<&str>, addr| {
+            let bind_addr = bind_addr.map(|a| a.parse().unwrap());
+            let socket = block_on(bind_udp(bind_addr, addr)).unwrap();
+            socket.local_addr().unwrap().ip().to_string()
+        };
+        assert_eq!(local_ip(None, v4_addr), "0.0.0.0");
+        assert_eq!(local_ip(Some("127.0.0.1"), v4_addr), "127.0.0.1");
+        assert_eq!(local_ip(Some("::1"), v4_addr), "0.0.0.0");
+        assert_eq!(local_ip(Some("127.0.0.1"), v6_addr), "::");
+    }
+
+    #[test]
+    fn test_connect_tcp_ignore_bind_addr_of_other_family() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bind_addr = Some("::1".parse().unwrap());
+        assert!(block_on(connect_tcp(bind_addr, addr, Duration::from_secs(1))).is_ok());
+    }
+}
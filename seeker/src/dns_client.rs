@@ -3,18 +3,74 @@ use async_std_resolver::config::{
 };
 use async_std_resolver::{resolver, AsyncStdResolver};
 use config::Address;
+use lru_cache::LruCache;
+use parking_lot::Mutex;
 use std::io::{Error, ErrorKind, Result};
 use std::net::IpAddr;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const PINNED_DOMAINS_CAPACITY: usize = 4096;
 
 #[derive(Clone)]
 pub struct DnsClient {
     resolver: AsyncStdResolver,
+    pinning: Option<Arc<DnsPinningCache>>,
+}
+
+/// IPs a domain first resolved to, to detect spoofed DNS responses.
+///
+/// CDNs rotate IPs, so only a response sharing no IP with the pinned ones counts as a change.
+/// The new IPs are pinned after a change, so that a domain that moved is logged once. Changes
+/// are only logged, traffic is never blocked.
+struct DnsPinningCache {
+    pinned: Mutex<LruCache<String, Vec<IpAddr>>>,
+}
+
+impl DnsPinningCache {
+    fn new() -> Self {
+        DnsPinningCache {
+            pinned: Mutex::new(LruCache::new(PINNED_DOMAINS_CAPACITY)),
+        }
+    }
+
+    /// Pin `ips` if `domain` is seen for the first time, otherwise return whether `ips`
+    /// changed from the pinned IPs, pinning `ips` if so.
+    fn check(&self, domain: &str, ips: &[IpAddr], ttl_remaining: Duration) -> bool {
+        let mut pinned = self.pinned.lock();
+        match pinned.get_mut(domain) {
+            Some(old_ips) => {
+                let changed = !ips.iter().any(|ip| old_ips.contains(ip));
+                if changed {
+                    warn!(
+                        domain,
+                        old_ip = ?old_ips,
+                        new_ip = ?ips,
+                        ttl_remaining = ttl_remaining.as_secs(),
+                        "DNS response changed unexpectedly"
+                    );
+                    *old_ips = ips.to_vec();
+                }
+                changed
+            }
+            None => {
+                pinned.insert(domain.to_string(), ips.to_vec());
+                false
+            }
+        }
+    }
 }
 
 impl DnsClient {
-    pub async fn new(dns_servers: &[SocketAddr], timeout: Duration) -> Self {
+    /// `negative_ttl` is the minimal time NXDOMAIN responses are cached for, so that lookups
+    /// of non-existent domains are not sent again on every connection.
+    pub async fn new(
+        dns_servers: &[SocketAddr],
+        timeout: Duration,
+        negative_ttl: Duration,
+    ) -> Self {
         let mut name_servers = NameServerConfigGroup::with_capacity(dns_servers.len());
 
         for addr in dns_servers {
@@ -34,13 +90,23 @@ impl DnsClient {
             ResolverOpts {
                 timeout,
                 num_concurrent_reqs,
+                negative_min_ttl: Some(negative_ttl),
                 ..Default::default()
             },
         )
         .await
         .expect("failed to create resolver");
 
-        DnsClient { resolver }
+        DnsClient {
+            resolver,
+            pinning: None,
+        }
+    }
+
+    /// Warn when a domain resolves to IPs that have nothing in common with its first
+    /// resolution.
+    pub fn enable_change_alert(&mut self) {
+        self.pinning = Some(Arc::new(DnsPinningCache::new()));
     }
 
     pub fn resolver(&self) -> AsyncStdResolver {
@@ -52,6 +118,13 @@ impl DnsClient {
             .lookup_ip(domain)
             .await
             .map_err(|_| Error::new(ErrorKind::NotFound, format!("{} not resolved", domain)))?;
+        if let Some(pinning) = &self.pinning {
+            let ips: Vec<IpAddr> = response.iter().collect();
+            let ttl_remaining = response
+                .valid_until()
+                .saturating_duration_since(Instant::now());
+            pinning.check(domain, &ips, ttl_remaining);
+        }
         response
             .iter()
             .next()
@@ -68,3 +141,21 @@ impl DnsClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dns_pinning_cache() {
+        let cache = DnsPinningCache::new();
+        let ttl = Duration::from_secs(60);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(!cache.check("example.com", &[ip("1.1.1.1"), ip("1.1.1.2")], ttl));
+        assert!(!cache.check("example.com", &[ip("1.1.1.2"), ip("1.1.1.3")], ttl));
+        assert!(cache.check("example.com", &[ip("2.2.2.2")], ttl));
+        assert!(!cache.check("example.com", &[ip("2.2.2.2")], ttl));
+        assert!(cache.check("example.com", &[ip("1.1.1.1")], ttl));
+        assert!(!cache.check("example.org", &[ip("2.2.2.2")], ttl));
+    }
+}
@@ -0,0 +1,120 @@
+use crate::bind::connect_tcp;
+use crate::dns_client::DnsClient;
+use crate::proxy_client::CONNECTIVITY_PROBE_ADDR;
+use async_std::task::block_on;
+use config::{Address, Config};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::os::unix::fs::PermissionsExt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// Domain looked up to check that a dns server answers.
+pub const DNS_CHECK_DOMAIN: &str = "example.com";
+
+/// Run every diagnostic check, print a pass/fail line for each of them and return the
+/// number of failed checks.
+pub fn run_doctor(paths: &[&str], config: &Config, uid: Option<u32>) -> usize {
+    let mut failures = 0;
+    let mut report = |name: &str, check: &dyn Fn() -> Result<(), String>| {
+        // A check must never bring the whole report down.
+        let ret = catch_unwind(AssertUnwindSafe(check))
+            .unwrap_or_else(|_| Err("check panicked".to_string()));
+        match ret {
+            Ok(()) => println!("\x1b[32m[PASS]\x1b[0m {}", name),
+            Err(e) => {
+                failures += 1;
+                println!("\x1b[31m[FAIL]\x1b[0m {}: {}", name, e);
+            }
+        }
+    };
+
+    for path in paths {
+        report(
+            &format!("config file {} is not world-writable", path),
+            &|| check_config_permissions(path),
+        );
+    }
+    report("tun device can be created", &|| {
+        tun_nat::check_tun_device(&config.tun_name)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    });
+    report(
+        &format!("dns listen address {}", config.dns_listen),
+        &|| {
+            UdpSocket::bind(&config.dns_listen)
+                .map(|_| ())
+                .map_err(|e| format!("can not bind, is another dns server running? {}", e))
+        },
+    );
+    for dns_server in &config.dns_servers {
+        report(&format!("dns server {}", dns_server), &|| {
+            block_on(async {
+                let client =
+                    DnsClient::new(&[*dns_server], config.dns_timeout, config.dns_negative_ttl)
+                        .await;
+                client.lookup(DNS_CHECK_DOMAIN).await
+            })
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        });
+    }
+    if let Some(servers) = &config.shadowsocks_servers {
+        for server in servers.iter() {
+            let name = format!("shadowsocks server {}({})", server.name(), server.addr());
+            report(&name, &|| {
+                check_tcp_reachable(config, config.proxy_bind_addr, server.addr())
+            });
+        }
+    }
+    let probe_addr = Address::SocketAddress(SocketAddr::from(CONNECTIVITY_PROBE_ADDR));
+    report(&format!("direct connection to {}", probe_addr), &|| {
+        check_tcp_reachable(config, config.direct_bind_addr, &probe_addr)
+    });
+    if let Some(uid) = uid {
+        report(&format!("uid {} is a real user", uid), &|| check_uid(uid));
+    }
+
+    failures
+}
+
+fn check_config_permissions(path: &str) -> Result<(), String> {
+    let mode = std::fs::metadata(path)
+        .map_err(|e| e.to_string())?
+        .permissions()
+        .mode();
+    if mode & 0o002 != 0 {
+        return Err(format!("{} has mode {:o}", path, mode & 0o777));
+    }
+    Ok(())
+}
+
+/// Connect to `addr` from `bind_addr`, like relayed connections do.
+fn check_tcp_reachable(
+    config: &Config,
+    bind_addr: Option<IpAddr>,
+    addr: &Address,
+) -> Result<(), String> {
+    block_on(async {
+        let client = DnsClient::new(
+            &config.dns_servers,
+            config.dns_timeout,
+            config.dns_negative_ttl,
+        )
+        .await;
+        let addr = client
+            .lookup_address(addr)
+            .await
+            .map_err(|e| e.to_string())?;
+        connect_tcp(bind_addr, addr, config.connect_timeout)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    })
+}
+
+fn check_uid(uid: u32) -> Result<(), String> {
+    if unsafe { libc::getpwuid(uid) }.is_null() {
+        return Err("no such user".to_string());
+    }
+    Ok(())
+}
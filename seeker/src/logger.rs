@@ -1,3 +1,4 @@
+use config::LogFormat;
 use file_rotate::{FileRotate, RotationMode};
 use std::error::Error;
 use std::io;
@@ -28,7 +29,7 @@ impl io::Write for TracingWriter {
     }
 }
 
-pub fn setup_logger(log_path: Option<&str>) -> Result<(), Box<dyn Error>> {
+pub fn setup_logger(log_path: Option<&str>, log_format: LogFormat) -> Result<(), Box<dyn Error>> {
     let env_filter = EnvFilter::new("seeker=trace")
         .add_directive("dnsserver=debug".parse()?)
         .add_directive("seeker=trace".parse()?)
@@ -44,21 +45,22 @@ pub fn setup_logger(log_path: Option<&str>) -> Result<(), Box<dyn Error>> {
             RotationMode::Lines(100_000),
             20,
         )));
-        let my_subscriber = FmtSubscriber::builder()
+        let builder = FmtSubscriber::builder()
             .with_env_filter(env_filter)
             .with_ansi(false)
-            .with_writer(move || TracingWriter::new(logger.clone()))
-            .finish();
-        tracing::subscriber::set_global_default(my_subscriber)
-            .expect("setting tracing default failed");
+            .with_writer(move || TracingWriter::new(logger.clone()));
+        match log_format {
+            LogFormat::Text => tracing::subscriber::set_global_default(builder.finish()),
+            LogFormat::Json => tracing::subscriber::set_global_default(builder.json().finish()),
+        }
+        .expect("setting tracing default failed");
     } else {
-        let subscriber = FmtSubscriber::builder()
-            .with_env_filter(env_filter)
-            .compact()
-            .finish();
-
-        tracing::subscriber::set_global_default(subscriber)
-            .expect("setting tracing default failed");
+        let builder = FmtSubscriber::builder().with_env_filter(env_filter);
+        match log_format {
+            LogFormat::Text => tracing::subscriber::set_global_default(builder.compact().finish()),
+            LogFormat::Json => tracing::subscriber::set_global_default(builder.json().finish()),
+        }
+        .expect("setting tracing default failed");
     };
 
     // #[cfg(debug_assertions)]
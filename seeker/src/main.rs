@@ -1,12 +1,23 @@
 #[macro_use]
 mod macros;
+mod bind;
 mod config_encryptor;
 mod dns_client;
+mod doctor;
 mod logger;
+mod ping;
+mod plugin;
+#[cfg(target_arch = "x86_64")]
+mod proc_sock_cache;
 mod proxy_client;
 mod proxy_tcp_stream;
 mod proxy_udp_socket;
+mod rate_limiter;
+mod remote_rules;
 mod server_chooser;
+mod stats;
+mod tcp_log;
+mod update_check;
 
 use std::error::Error;
 
@@ -15,11 +26,12 @@ use crate::proxy_client::ProxyClient;
 use anyhow::Context;
 use async_signals::Signals;
 use async_std::prelude::{FutureExt, StreamExt};
-use async_std::task::block_on;
-use clap::{App, Arg};
+use async_std::task::{block_on, spawn};
+use clap::{App, Arg, SubCommand};
 use config::Config;
 use crypto::CipherType;
 use std::fs::File;
+use std::time::Duration;
 use sysconfig::{set_rlimit_no_file, DNSSetup, IpForward};
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -33,7 +45,9 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .short("c")
                 .long("config")
                 .value_name("FILE")
-                .help("Sets config file. Sample config at https://github.com/gfreezy/seeker/blob/master/sample_config.yml")
+                .help("Sets config file, may be repeated to merge several files. Sample config at https://github.com/gfreezy/seeker/blob/master/sample_config.yml")
+                .multiple(true)
+                .number_of_values(1)
                 .required(false),
         )
         .arg(
@@ -72,25 +86,86 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .help("Log file")
                 .required(false),
         )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Diagnose common configuration and environment problems"),
+        )
+        .subcommand(
+            SubCommand::with_name("ping")
+                .about("Measure TCP connect time to the shadowsocks servers")
+                .arg(
+                    Arg::with_name("count")
+                        .short("n")
+                        .long("count")
+                        .value_name("N")
+                        .help("Connections per server")
+                        .default_value("4")
+                        .validator(|v| match v.parse::<usize>() {
+                            Ok(n) if n > 0 => Ok(()),
+                            _ => Err("must be a positive integer".to_string()),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .value_name("MS")
+                        .help("Milliseconds between connections")
+                        .default_value("1000"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print results as JSON"),
+                ),
+        )
         .get_matches();
 
-    let path = matches.value_of("config");
+    let paths: Vec<&str> = matches
+        .values_of("config")
+        .map(|values| values.collect())
+        .unwrap_or_default();
     let key = matches.value_of("key");
     let to_encrypt = matches.is_present("encrypt");
     if to_encrypt {
         println!(
             "Encrypted content is as below:\n\n\n{}\n\n",
-            encrypt_config(path, key)?
+            encrypt_config(paths.first().copied(), key)?
         );
         return Ok(());
     }
     let config_url = matches.value_of("config-url");
-    let config = load_config(path, config_url, key)?;
-
     let uid = matches.value_of("user_id").map(|uid| uid.parse().unwrap());
+    if matches.subcommand_matches("doctor").is_some() {
+        let failures = match load_config(&paths, config_url, key) {
+            Ok(config) => doctor::run_doctor(&paths, &config, uid),
+            Err(e) => {
+                println!("\x1b[31m[FAIL]\x1b[0m load config: {:?}", e);
+                1
+            }
+        };
+        std::process::exit(failures as i32);
+    }
+
+    if let Some(ping_matches) = matches.subcommand_matches("ping") {
+        let count = ping_matches.value_of("count").unwrap().parse()?;
+        let interval = Duration::from_millis(ping_matches.value_of("interval").unwrap().parse()?);
+        let config = load_config(&paths, config_url, key)?;
+        let unreachable = block_on(ping::run_ping(
+            &config,
+            count,
+            interval,
+            ping_matches.is_present("json"),
+        ));
+        std::process::exit(unreachable as i32);
+    }
+
+    let mut config = load_config(&paths, config_url, key)?;
+
     let log_path = matches.value_of("log");
 
-    setup_logger(log_path)?;
+    setup_logger(log_path, config.log_format)?;
+
+    remote_rules::apply_remote_rules(&mut config);
 
     let mut signals = Signals::new(vec![libc::SIGINT, libc::SIGTERM]).unwrap();
 
@@ -104,27 +179,33 @@ fn main() -> Result<(), Box<dyn Error>> {
         None
     };
 
+    let check_for_updates = config.check_for_updates;
     block_on(async {
-        let client = ProxyClient::new(config, uid).await;
+        let client = ProxyClient::new(config, uid).await?;
+        if check_for_updates {
+            spawn(update_check::check_for_updates_forever());
+        }
         client
             .run()
             .race(async {
                 signals.next().await.unwrap();
             })
             .await;
-    });
+        Ok::<(), std::io::Error>(())
+    })?;
 
     println!("Stop server. Bye bye...");
     Ok(())
 }
 
 fn load_config(
-    path: Option<&str>,
+    paths: &[&str],
     url: Option<&str>,
     decrypt_key: Option<&str>,
 ) -> anyhow::Result<Config> {
-    match (path, url, decrypt_key) {
-        (Some(p), ..) => Config::from_config_file(p).context("Load config from path error"),
+    match (paths, url, decrypt_key) {
+        ([p], ..) => Config::from_config_file(p).context("Load config from path error"),
+        ([_, ..], ..) => Config::from_config_files(paths).context("Load config from paths error"),
         (_, Some(url), Some(key)) => {
             let resp = ureq::get(url)
                 .timeout_read(5000)
@@ -0,0 +1,158 @@
+use crate::bind::connect_tcp;
+use crate::dns_client::DnsClient;
+use async_std::task::sleep;
+use config::Config;
+use std::time::{Duration, Instant};
+
+/// TCP connect times to one shadowsocks server.
+struct PingStats {
+    name: String,
+    addr: String,
+    sent: usize,
+    rtts: Vec<Duration>,
+}
+
+impl PingStats {
+    fn rtts_ms(&self) -> impl Iterator<Item = f64> + '_ {
+        self.rtts.iter().map(|d| d.as_micros() as f64 / 1000.0)
+    }
+
+    fn min(&self) -> Option<f64> {
+        self.rtts_ms()
+            .fold(None, |min, rtt| Some(min.map_or(rtt, |m: f64| m.min(rtt))))
+    }
+
+    fn max(&self) -> Option<f64> {
+        self.rtts_ms()
+            .fold(None, |max, rtt| Some(max.map_or(rtt, |m: f64| m.max(rtt))))
+    }
+
+    fn avg(&self) -> Option<f64> {
+        if self.rtts.is_empty() {
+            return None;
+        }
+        Some(self.rtts_ms().sum::<f64>() / self.rtts.len() as f64)
+    }
+
+    /// Mean difference between consecutive RTTs.
+    fn jitter(&self) -> Option<f64> {
+        let rtts: Vec<f64> = self.rtts_ms().collect();
+        if rtts.len() < 2 {
+            return None;
+        }
+        let diffs: f64 = rtts.windows(2).map(|w| (w[1] - w[0]).abs()).sum();
+        Some(diffs / (rtts.len() - 1) as f64)
+    }
+
+    fn loss_percent(&self) -> f64 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        (self.sent - self.rtts.len()) as f64 * 100.0 / self.sent as f64
+    }
+}
+
+/// Connect to every shadowsocks server `count` times and print the RTT statistics of each
+/// server. Returns the number of servers that could not be reached at all.
+pub async fn run_ping(config: &Config, count: usize, interval: Duration, json: bool) -> usize {
+    let dns_client = DnsClient::new(
+        &config.dns_servers,
+        config.dns_timeout,
+        config.dns_negative_ttl,
+    )
+    .await;
+    let mut results = vec![];
+    for server in config.shadowsocks_servers.iter().flat_map(|s| s.iter()) {
+        let mut stats = PingStats {
+            name: server.name().to_string(),
+            addr: server.addr().to_string(),
+            sent: count,
+            rtts: vec![],
+        };
+        if let Ok(addr) = dns_client.lookup_address(server.tcp_addr()).await {
+            for i in 0..count {
+                if i > 0 {
+                    sleep(interval).await;
+                }
+                let start = Instant::now();
+                let ret = connect_tcp(config.proxy_bind_addr, addr, config.ping_timeout);
+                if ret.await.is_ok() {
+                    stats.rtts.push(start.elapsed());
+                }
+            }
+        }
+        results.push(stats);
+    }
+
+    if json {
+        print_json(&results);
+    } else {
+        print_table(&results);
+    }
+    results.iter().filter(|s| s.rtts.is_empty()).count()
+}
+
+fn print_table(results: &[PingStats]) {
+    let ms = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.1}", v));
+    println!(
+        "{:<16} {:<32} {:>5} {:>8} {:>8} {:>8} {:>8} {:>6}",
+        "NAME", "ADDR", "SENT", "MIN", "AVG", "MAX", "JITTER", "LOSS"
+    );
+    for s in results {
+        println!(
+            "{:<16} {:<32} {:>5} {:>8} {:>8} {:>8} {:>8} {:>5.0}%",
+            s.name,
+            s.addr,
+            s.sent,
+            ms(s.min()),
+            ms(s.avg()),
+            ms(s.max()),
+            ms(s.jitter()),
+            s.loss_percent()
+        );
+    }
+}
+
+fn print_json(results: &[PingStats]) {
+    let results: Vec<_> = results
+        .iter()
+        .map(|s| {
+            serde_json::json!({
+                "name": s.name,
+                "addr": s.addr,
+                "sent": s.sent,
+                "received": s.rtts.len(),
+                "min_ms": s.min(),
+                "avg_ms": s.avg(),
+                "max_ms": s.max(),
+                "jitter_ms": s.jitter(),
+                "loss_percent": s.loss_percent(),
+            })
+        })
+        .collect();
+    println!("{}", serde_json::Value::Array(results));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ping_stats() {
+        let stats = PingStats {
+            name: "hk".to_string(),
+            addr: "hk.example.com:8388".to_string(),
+            sent: 4,
+            rtts: vec![
+                Duration::from_millis(10),
+                Duration::from_millis(30),
+                Duration::from_millis(20),
+            ],
+        };
+        assert_eq!(stats.min(), Some(10.0));
+        assert_eq!(stats.max(), Some(30.0));
+        assert_eq!(stats.avg(), Some(20.0));
+        assert_eq!(stats.jitter(), Some(15.0));
+        assert!((stats.loss_percent() - 25.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,100 @@
+use async_std::io::timeout;
+use async_std::net::TcpStream;
+use async_std::task::sleep;
+use config::{Address, ShadowsocksServerConfig};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+const LISTEN_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A SIP003 plugin process running for one shadowsocks server.
+///
+/// The plugin listens on a local port and forwards to the remote server, so TCP connections
+/// to the server go to `local_addr` instead. The process is killed on drop.
+pub struct SsPlugin {
+    child: Child,
+    local_addr: SocketAddr,
+}
+
+impl SsPlugin {
+    /// Start the plugin of `config` and wait until it accepts connections, returns `None` if
+    /// no plugin is configured.
+    pub async fn start(config: &ShadowsocksServerConfig) -> Result<Option<SsPlugin>> {
+        let plugin = match config.plugin() {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        let (remote_host, remote_port) = match config.addr() {
+            Address::SocketAddress(addr) => (addr.ip().to_string(), addr.port()),
+            Address::DomainNameAddress(domain, port) => (domain.clone(), *port),
+        };
+        let local_addr = pick_local_addr()?;
+
+        let mut cmd = Command::new(plugin);
+        cmd.env("SS_REMOTE_HOST", remote_host)
+            .env("SS_REMOTE_PORT", remote_port.to_string())
+            .env("SS_LOCAL_HOST", local_addr.ip().to_string())
+            .env("SS_LOCAL_PORT", local_addr.port().to_string());
+        if let Some(opts) = config.plugin_opts() {
+            cmd.env("SS_PLUGIN_OPTIONS", opts);
+        }
+        let child = cmd.spawn()?;
+        info!(
+            name = config.name(),
+            plugin,
+            pid = child.id(),
+            %local_addr,
+            "Start shadowsocks plugin"
+        );
+        let mut plugin = SsPlugin { child, local_addr };
+        plugin.wait_listening().await?;
+        Ok(Some(plugin))
+    }
+
+    /// Wait until the plugin accepts connections on `local_addr`, so that the first
+    /// connections through it don't fail. Errors if the plugin exits or doesn't listen
+    /// within `START_TIMEOUT`.
+    async fn wait_listening(&mut self) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            let connect = TcpStream::connect(self.local_addr);
+            if timeout(LISTEN_CHECK_INTERVAL, connect).await.is_ok() {
+                return Ok(());
+            }
+            if let Some(status) = self.child.try_wait()? {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("plugin exited: {}", status),
+                ));
+            }
+            if start.elapsed() > START_TIMEOUT {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("plugin not listening on {}", self.local_addr),
+                ));
+            }
+            sleep(LISTEN_CHECK_INTERVAL).await;
+        }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for SsPlugin {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Ask the OS for a free port on the loopback interface.
+fn pick_local_addr() -> Result<SocketAddr> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    listener.local_addr()
+}
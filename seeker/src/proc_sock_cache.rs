@@ -0,0 +1,208 @@
+use lru_cache::LruCache;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io::Result;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sysconfig::{SocketInfo, SocketProtocol};
+
+type UserProcSocks = HashMap<i32, Vec<SocketInfo>>;
+
+const RESULT_CAPACITY: usize = 1024;
+
+/// Cache of `sysconfig::list_user_proc_socks`.
+///
+/// Every new connection needs to know whether its source socket belongs to the proxied user,
+/// and each lookup is a full scan of `/proc`. The scan result is reused for `ttl` when it has
+/// the socket, a socket missing from it triggers a new scan. Owners found are cached for `ttl`
+/// too, for SYN retransmissions that ask about the same socket again. A `ttl` of 0 disables
+/// caching.
+#[derive(Clone)]
+pub struct ProcSockCache {
+    data: Arc<Mutex<Option<(u32, Arc<UserProcSocks>, Instant)>>>,
+    ttl: Duration,
+    results: Arc<Mutex<LruCache<(SocketAddr, SocketProtocol, u32), (i32, Instant)>>>,
+}
+
+impl ProcSockCache {
+    pub fn new(ttl: Duration) -> Self {
+        ProcSockCache {
+            data: Arc::new(Mutex::new(None)),
+            ttl,
+            results: Arc::new(Mutex::new(LruCache::new(RESULT_CAPACITY))),
+        }
+    }
+
+    /// Pid of the process of `uid` owning the local `protocol` socket `addr`, `None` if no
+    /// process of `uid` owns it.
+    pub fn socket_owner_pid(
+        &self,
+        addr: SocketAddr,
+        protocol: SocketProtocol,
+        uid: u32,
+    ) -> Result<Option<i32>> {
+        let key = (addr, protocol, uid);
+        if let Some((pid, updated_at)) = self.results.lock().get_mut(&key) {
+            if updated_at.elapsed() < self.ttl {
+                return Ok(Some(*pid));
+            }
+        }
+        let find_pid = |user_socks: &UserProcSocks| find_socket_pid(user_socks, protocol, addr);
+        // Only trust the cached scan when it has the socket, a miss may be a socket opened
+        // after the scan.
+        let mut pid = self.cached_user_proc_socks(uid).and_then(|s| find_pid(&s));
+        if pid.is_none() {
+            pid = find_pid(&self.scan_user_proc_socks(uid)?);
+        }
+        // Only cache owners found, a socket missing now may be opened by `uid` right after.
+        if let Some(pid) = pid {
+            self.results.lock().insert(key, (pid, Instant::now()));
+        }
+        Ok(pid)
+    }
+
+    /// Pid and uid of the process owning the local `protocol` socket `addr`, whatever its
+    /// user. This scans all processes and is not cached, it is only used to log the owners of
+    /// connections passed by the uid filter.
+    pub fn socket_owner(
+        &self,
+        addr: SocketAddr,
+        protocol: SocketProtocol,
+    ) -> Result<Option<(i32, u32)>> {
+        let socks = sysconfig::list_system_proc_socks()?;
+        match find_socket_pid(&socks, protocol, addr) {
+            Some(pid) => Ok(Some((pid, sysconfig::process_owner(pid)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Sockets of `uid` from the last scan, if it is younger than `ttl`.
+    fn cached_user_proc_socks(&self, uid: u32) -> Option<Arc<UserProcSocks>> {
+        match &*self.data.lock() {
+            Some((cached_uid, socks, updated_at))
+                if *cached_uid == uid && updated_at.elapsed() < self.ttl =>
+            {
+                Some(socks.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Scan `/proc` for the sockets of `uid` and cache the result. The lock is not held while
+    /// scanning, so that other connections are not blocked by the scan.
+    fn scan_user_proc_socks(&self, uid: u32) -> Result<Arc<UserProcSocks>> {
+        let socks = Arc::new(sysconfig::list_user_proc_socks(uid)?);
+        *self.data.lock() = Some((uid, socks.clone(), Instant::now()));
+        Ok(socks)
+    }
+}
+
+/// Pid of the process in `socks` owning the local `protocol` socket `addr`.
+fn find_socket_pid(
+    socks: &UserProcSocks,
+    protocol: SocketProtocol,
+    addr: SocketAddr,
+) -> Option<i32> {
+    socks
+        .iter()
+        .find(|(_, sockets)| sockets.iter().any(|s| has_local_addr(s, protocol, addr)))
+        .map(|(pid, _)| *pid)
+}
+
+/// Whether `socket` is the `protocol` socket bound to `addr`. Unconnected UDP sockets are
+/// listed with an unspecified local ip, they are matched by port. TCP sockets with an
+/// unspecified local ip are listeners, they never match an outgoing connection.
+fn has_local_addr(socket: &SocketInfo, protocol: SocketProtocol, addr: SocketAddr) -> bool {
+    if socket.protocol != protocol {
+        return false;
+    }
+    socket.local == addr
+        || (protocol == SocketProtocol::Udp
+            && socket.local.ip().is_unspecified()
+            && socket.remote.ip().is_unspecified()
+            && socket.local.port() == addr.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_local_addr() {
+        let socket = |local: &str, remote: &str, protocol| SocketInfo {
+            local: local.parse().unwrap(),
+            remote: remote.parse().unwrap(),
+            protocol,
+        };
+        let udp = |local: &str| socket(local, "0.0.0.0:0", SocketProtocol::Udp);
+        let addr = "10.0.0.2:5353".parse().unwrap();
+        let has_udp_addr = |s: &SocketInfo| has_local_addr(s, SocketProtocol::Udp, addr);
+        assert!(has_udp_addr(&udp("10.0.0.2:5353")));
+        assert!(has_udp_addr(&udp("0.0.0.0:5353")));
+        assert!(has_udp_addr(&udp("[::]:5353")));
+        assert!(!has_udp_addr(&udp("10.0.0.3:5353")));
+        assert!(!has_udp_addr(&udp("0.0.0.0:5354")));
+        // connected udp socket
+        let connected = socket("0.0.0.0:5353", "8.8.8.8:53", SocketProtocol::Udp);
+        assert!(!has_udp_addr(&connected));
+
+        let has_tcp_addr = |s: &SocketInfo| has_local_addr(s, SocketProtocol::Tcp, addr);
+        let listener = socket("0.0.0.0:5353", "0.0.0.0:0", SocketProtocol::Tcp);
+        assert!(!has_tcp_addr(&listener));
+        assert!(!has_tcp_addr(&udp("10.0.0.2:5353")));
+        let stream = socket("10.0.0.2:5353", "1.2.3.4:443", SocketProtocol::Tcp);
+        assert!(has_tcp_addr(&stream));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_unconnected_udp_socket_owner() {
+        let uid = unsafe { libc::getuid() };
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), socket.local_addr().unwrap().port());
+        let cache = ProcSockCache::new(Duration::from_secs(1));
+        assert_eq!(
+            cache
+                .socket_owner_pid(addr, SocketProtocol::Udp, uid)
+                .unwrap(),
+            Some(std::process::id() as i32)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_socket_owner() {
+        let uid = unsafe { libc::getuid() };
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let cache = ProcSockCache::new(Duration::from_secs(1));
+        assert_eq!(
+            cache.socket_owner(addr, SocketProtocol::Udp).unwrap(),
+            Some((std::process::id() as i32, uid))
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_tcp_listener_and_udp_socket_on_same_port() {
+        let uid = unsafe { libc::getuid() };
+        let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let _socket = std::net::UdpSocket::bind(("0.0.0.0", port)).unwrap();
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), port);
+        let cache = ProcSockCache::new(Duration::from_secs(1));
+        assert_eq!(
+            cache
+                .socket_owner_pid(addr, SocketProtocol::Udp, uid)
+                .unwrap(),
+            Some(std::process::id() as i32)
+        );
+        assert_eq!(
+            cache
+                .socket_owner_pid(addr, SocketProtocol::Tcp, uid)
+                .unwrap(),
+            None
+        );
+    }
+}
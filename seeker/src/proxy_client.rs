@@ -1,44 +1,108 @@
+use crate::bind::{bind_udp, check_bind_addr, check_port_reachability, connect_tcp};
 use crate::dns_client::DnsClient;
+use crate::doctor::DNS_CHECK_DOMAIN;
+use crate::plugin::SsPlugin;
+#[cfg(target_arch = "x86_64")]
+use crate::proc_sock_cache::ProcSockCache;
 use crate::proxy_tcp_stream::ProxyTcpStream;
 use crate::proxy_udp_socket::ProxyUdpSocket;
+use crate::rate_limiter::RateLimiter;
 use crate::server_chooser::ShadowsocksServerChooser;
+use crate::stats::ConnectionStats;
+use crate::tcp_log::{ConnectionLog, HexDump, TcpConnectionLogger};
 use async_std::io::{timeout, Read, Write};
-use async_std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use async_std::net::{SocketAddr, TcpListener, UdpSocket};
 use async_std::prelude::*;
-use async_std::task::spawn;
+use async_std::task::{sleep, spawn};
 use async_std_resolver::AsyncStdResolver;
 use config::rule::Action;
 use config::{Address, Config};
 use dnsserver::create_dns_server;
 use dnsserver::resolver::RuleBasedDnsResolver;
 use http_proxy_client::HttpProxyTcpStream;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use socks5_client::{Socks5TcpStream, Socks5UdpSocket};
-use ssclient::{SSTcpStream, SSUdpSocket};
+use ssclient::{proxy_protocol_v2_header, SSTcpStream, SSUdpSocket};
 use std::collections::HashMap;
 use std::io;
 use std::io::Result;
 use std::sync::Arc;
-use tracing::{error, trace, trace_span};
+use std::time::{Duration, Instant};
+use sysconfig::SocketProtocol;
+use tracing::{debug, error, field, info, trace, trace_span, warn, Span};
 use tracing_futures::Instrument;
 use tun_nat::{run_nat, SessionManager};
 
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+pub const CONNECTIVITY_PROBE_ADDR: ([u8; 4], u16) = ([8, 8, 8, 8], 443);
+
+/// Socket relaying a UDP session, its destination, and when a datagram was last sent or
+/// received in either direction.
+type UdpSession = (ProxyUdpSocket, SocketAddr, Arc<Mutex<Instant>>);
+
+/// Outcome of one check run by `ProxyClient::test_connectivity`.
+#[derive(Debug)]
+pub struct ConnectivityResult {
+    pub target: String,
+    pub ok: bool,
+    pub latency: Duration,
+    pub error: Option<String>,
+}
+
+impl ConnectivityResult {
+    fn new(target: String, start: Instant, ret: Result<()>) -> Self {
+        ConnectivityResult {
+            target,
+            ok: ret.is_ok(),
+            latency: start.elapsed(),
+            error: ret.err().map(|e| e.to_string()),
+        }
+    }
+}
+
 pub struct ProxyClient {
     config: Config,
     uid: Option<u32>,
     session_manager: SessionManager,
-    udp_manager: Arc<RwLock<HashMap<u16, (ProxyUdpSocket, SocketAddr)>>>,
+    udp_manager: Arc<RwLock<HashMap<u16, UdpSession>>>,
     resolver: RuleBasedDnsResolver,
     dns_client: DnsClient,
     extra_directly_servers: Vec<String>,
     ss_server_chooser: Option<Arc<ShadowsocksServerChooser>>,
+    stats: ConnectionStats,
+    probe_rate_limiter: RateLimiter,
+    tcp_logger: Option<TcpConnectionLogger>,
+    #[cfg(target_arch = "x86_64")]
+    proc_sock_cache: ProcSockCache,
+    _plugins: Vec<SsPlugin>,
 }
 
 impl ProxyClient {
-    pub async fn new(config: Config, uid: Option<u32>) -> Self {
+    pub async fn new(mut config: Config, uid: Option<u32>) -> Result<Self> {
+        if let Some(addr) = config.direct_bind_addr {
+            check_bind_addr("direct_bind_addr", addr)?;
+        }
+        if let Some(addr) = config.proxy_bind_addr {
+            check_bind_addr("proxy_bind_addr", addr)?;
+        }
+        info!(
+            domain_rules = config.rules.domain_count(),
+            cidr_rules = config.rules.cidr_count(),
+            total_rules = config.rules.total_rule_count(),
+            "Load rules"
+        );
+        let plugins = start_plugins(&mut config).await?;
         let session_manager =
             run_nat(&config.tun_name, config.tun_ip, config.tun_cidr, 1300).expect("run nat");
-        let dns_client = DnsClient::new(&config.dns_servers, config.dns_timeout).await;
+        let mut dns_client = DnsClient::new(
+            &config.dns_servers,
+            config.dns_timeout,
+            config.dns_negative_ttl,
+        )
+        .await;
+        if config.dns_change_alert {
+            dns_client.enable_change_alert();
+        }
 
         let resolver = run_dns_resolver(&config, dns_client.resolver()).await;
 
@@ -60,42 +124,36 @@ impl ProxyClient {
 
         let server_chooser = match (&config.socks5_server, &config.shadowsocks_servers) {
             (None, Some(shadowsocks_servers)) => {
-                let ping_url = vec![
-                    (
-                        Address::DomainNameAddress("google.com".to_string(), 80),
-                        "/".to_string(),
-                    ),
-                    (
-                        Address::DomainNameAddress("twitter.com".to_string(), 80),
-                        "/".to_string(),
-                    ),
-                    (
-                        Address::DomainNameAddress("github.com".to_string(), 80),
-                        "/".to_string(),
-                    ),
-                    (
-                        Address::DomainNameAddress("youtube.com".to_string(), 80),
-                        "/".to_string(),
-                    ),
-                ];
                 let chooser = Arc::new(
                     ShadowsocksServerChooser::new(
                         shadowsocks_servers.clone(),
                         dns_client.clone(),
-                        ping_url,
+                        config.ping_urls.clone(),
                         config.ping_timeout,
+                        config.ping_interval,
+                        config.proxy_bind_addr,
                     )
                     .await,
                 );
                 let chooser_clone = chooser.clone();
-                let _ = spawn(async move { chooser_clone.ping_servers_forever().await.unwrap() });
+                let _ = spawn(
+                    async move { chooser_clone.ping_servers_forever().await.unwrap() }
+                        .instrument(Span::current()),
+                );
                 chooser.ping_servers().await;
                 Some(chooser)
             }
             _ => None,
         };
 
-        Self {
+        let client = Self {
+            #[cfg(target_arch = "x86_64")]
+            proc_sock_cache: ProcSockCache::new(config.proc_socks_cache_ttl),
+            probe_rate_limiter: RateLimiter::new(config.max_probes_per_second),
+            tcp_logger: match &config.debug_tcp_log {
+                Some(path) => Some(TcpConnectionLogger::new(path)?),
+                None => None,
+            },
             resolver,
             extra_directly_servers,
             udp_manager: Arc::new(RwLock::new(HashMap::new())),
@@ -104,20 +162,99 @@ impl ProxyClient {
             uid,
             session_manager,
             ss_server_chooser: server_chooser,
+            stats: ConnectionStats::default(),
+            _plugins: plugins,
+        };
+
+        let mut failed = 0;
+        for result in client.test_connectivity().await {
+            let latency = result.latency.as_millis();
+            if result.ok {
+                info!(check = %result.target, %latency, "connectivity check ok");
+            } else {
+                failed += 1;
+                error!(check = %result.target, %latency, error = ?result.error, "connectivity check failed");
+            }
         }
+        if failed > 0 && client.config.fail_on_connectivity_error {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{} connectivity checks failed", failed),
+            ));
+        }
+
+        Ok(client)
     }
 
+    /// Check that the shadowsocks servers and the dns servers can be reached, and that
+    /// there is a route to the internet.
+    pub async fn test_connectivity(&self) -> Vec<ConnectivityResult> {
+        let mut results = vec![];
+        if let Some(servers) = &self.config.shadowsocks_servers {
+            for server in servers.iter() {
+                let start = Instant::now();
+                let ret: Result<()> = async {
+                    let addr = self.dns_client.lookup_address(server.addr()).await?;
+                    connect_tcp(
+                        self.config.proxy_bind_addr,
+                        addr,
+                        self.config.connect_timeout,
+                    )
+                    .await?;
+                    Ok(())
+                }
+                .await;
+                let target = format!("shadowsocks server {}({})", server.name(), server.addr());
+                results.push(ConnectivityResult::new(target, start, ret));
+            }
+        }
+
+        for dns_server in &self.config.dns_servers {
+            let start = Instant::now();
+            let ret: Result<()> = async {
+                let client = DnsClient::new(
+                    &[*dns_server],
+                    self.config.dns_timeout,
+                    self.config.dns_negative_ttl,
+                )
+                .await;
+                client.lookup(DNS_CHECK_DOMAIN).await.map(|_| ())
+            }
+            .await;
+            let target = format!("dns server {}", dns_server);
+            results.push(ConnectivityResult::new(target, start, ret));
+        }
+
+        let probe_addr = SocketAddr::from(CONNECTIVITY_PROBE_ADDR);
+        let start = Instant::now();
+        let ret = if self.probe_connectivity(probe_addr).await {
+            Ok(())
+        } else {
+            Err(io::ErrorKind::TimedOut.into())
+        };
+        results.push(ConnectivityResult::new(
+            format!("probe {}", probe_addr),
+            start,
+            ret,
+        ));
+
+        results
+    }
+
+    /// Action for a connection from `original_addr` to `addr`, and whether Direct is forced
+    /// because the connection must not go through the proxy.
     async fn get_action_for_addr(
         &self,
         original_addr: SocketAddr,
+        protocol: SocketProtocol,
         socket_addr: SocketAddr,
         addr: &Address,
-    ) -> Result<Action> {
+    ) -> Result<(Action, bool)> {
         let mut pass_proxy = false;
         let domain = match &addr {
             // 如果是 IP 说明是用户手动改了路由表，必须要走代理。
             Address::SocketAddress(_) => {
-                return Ok(Action::Proxy);
+                return Ok((Action::Proxy, false));
             }
             Address::DomainNameAddress(domain, _port) => domain.to_string(),
         };
@@ -125,7 +262,24 @@ impl ProxyClient {
             pass_proxy = true;
         }
         if let Some(uid) = self.uid {
-            if !socket_addr_belong_to_user(original_addr, uid)? {
+            let (belongs, pid) = self.socket_addr_belong_to_user(original_addr, protocol, uid)?;
+            if let Some(pid) = pid {
+                record_socket_owner(pid, uid);
+            }
+            if !belongs {
+                let owner = self.socket_owner(original_addr, protocol);
+                if let Some((pid, owner_uid)) = owner {
+                    record_socket_owner(pid, owner_uid);
+                }
+                // Audit trail of connections that bypass the proxy
+                info!(
+                    src = ?original_addr,
+                    dest = ?addr,
+                    proxy_uid = uid,
+                    owner_uid = ?owner.map(|(_, uid)| uid),
+                    owner_pid = ?owner.map(|(pid, _)| pid),
+                    "Connection passed by uid filter"
+                );
                 pass_proxy = true;
             }
         }
@@ -139,27 +293,28 @@ impl ProxyClient {
         };
 
         if action == Action::Probe {
-            if self.probe_connectivity(socket_addr).await {
+            // Probing a network where direct connections always fail would flood the
+            // targets, fall back to proxy when probing too fast.
+            if !self.probe_rate_limiter.try_acquire() {
+                warn!(?addr, "Too many probes, fall back to proxy");
+                action = Action::Proxy;
+            } else if self.probe_connectivity(socket_addr).await {
                 action = Action::Direct;
             } else {
                 action = Action::Proxy;
             }
         }
 
-        Ok(action)
+        Ok((action, pass_proxy))
     }
 
     async fn choose_proxy_tcp_stream(
         &self,
-        original_addr: SocketAddr,
+        action: Action,
+        src_addr: SocketAddr,
         sock_addr: SocketAddr,
         remote_addr: &Address,
     ) -> Result<ProxyTcpStream> {
-        let action = self
-            .get_action_for_addr(original_addr, sock_addr, &remote_addr)
-            .await?;
-        trace!(?action, "selected action");
-
         match action {
             Action::Proxy => {
                 if let Some(socks5_config) = &self.config.socks5_server {
@@ -179,18 +334,33 @@ impl ProxyClient {
                     return retry!(3, async {
                         let (ss_server, server_alive) =
                             chooser.candidate().expect("no candidate available");
-                        let server = self.dns_client.lookup_address(&ss_server.addr()).await?;
-                        trace!("choose_proxy_tcp_stream: shadowsocks");
-                        let stream = timeout(
-                            self.config.connect_timeout,
-                            SSTcpStream::connect(
-                                remote_addr.clone(),
+                        let server = self.dns_client.lookup_address(ss_server.tcp_addr()).await?;
+                        trace!(
+                            server = ss_server.name(),
+                            "choose_proxy_tcp_stream: shadowsocks"
+                        );
+                        let proxy_header = if ss_server.proxy_protocol() {
+                            Some(proxy_protocol_v2_header(Some(src_addr), server))
+                        } else {
+                            None
+                        };
+                        let stream = timeout(self.config.connect_timeout, async {
+                            let stream = connect_tcp(
+                                self.config.proxy_bind_addr,
                                 server,
+                                self.config.connect_timeout,
+                            )
+                            .await?;
+                            SSTcpStream::connect_stream(
+                                stream,
+                                remote_addr.clone(),
                                 server_alive.clone(),
                                 ss_server.method(),
                                 ss_server.key(),
-                            ),
-                        )
+                                proxy_header,
+                            )
+                            .await
+                        })
                         .await;
                         match stream {
                             Ok(s) => Ok(ProxyTcpStream::Shadowsocks(s)),
@@ -226,7 +396,11 @@ impl ProxyClient {
             retry_timeout!(
                 self.config.connect_timeout,
                 self.config.max_connect_errors,
-                TcpStream::connect(sock_addr)
+                connect_tcp(
+                    self.config.direct_bind_addr,
+                    sock_addr,
+                    self.config.connect_timeout
+                )
             )
             .await?,
         ))
@@ -238,8 +412,8 @@ impl ProxyClient {
         sock_addr: SocketAddr,
         addr: &Address,
     ) -> Result<ProxyUdpSocket> {
-        let action = self
-            .get_action_for_addr(original_addr, sock_addr, &addr)
+        let (action, _) = self
+            .get_action_for_addr(original_addr, SocketProtocol::Udp, sock_addr, &addr)
             .await?;
 
         match action {
@@ -286,14 +460,55 @@ impl ProxyClient {
 
         trace!("choose_proxy_udp_socket: direct");
         Ok(ProxyUdpSocket::Direct(Arc::new(
-            UdpSocket::bind("0.0.0.0:0").await?,
+            bind_udp(self.config.direct_bind_addr, sock_addr).await?,
         )))
     }
 
+    /// Whether `addr` is a `protocol` socket of `uid`, and the pid owning it when known.
+    #[cfg(target_arch = "x86_64")]
+    fn socket_addr_belong_to_user(
+        &self,
+        addr: SocketAddr,
+        protocol: SocketProtocol,
+        uid: u32,
+    ) -> Result<(bool, Option<i32>)> {
+        let pid = self.proc_sock_cache.socket_owner_pid(addr, protocol, uid)?;
+        Ok((pid.is_some(), pid))
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn socket_addr_belong_to_user(
+        &self,
+        _addr: SocketAddr,
+        _protocol: SocketProtocol,
+        _uid: u32,
+    ) -> Result<(bool, Option<i32>)> {
+        Ok((true, None))
+    }
+
+    /// Pid and uid owning the `protocol` socket `addr`, whatever its user.
+    #[cfg(target_arch = "x86_64")]
+    fn socket_owner(&self, addr: SocketAddr, protocol: SocketProtocol) -> Option<(i32, u32)> {
+        self.proc_sock_cache
+            .socket_owner(addr, protocol)
+            .unwrap_or_else(|e| {
+                debug!(?e, ?addr, "Find socket owner error");
+                None
+            })
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn socket_owner(&self, _addr: SocketAddr, _protocol: SocketProtocol) -> Option<(i32, u32)> {
+        None
+    }
+
     async fn probe_connectivity(&self, addr: SocketAddr) -> bool {
-        timeout(self.config.probe_timeout, TcpStream::connect(addr))
-            .await
-            .is_ok()
+        check_port_reachability(
+            self.config.direct_bind_addr,
+            addr,
+            self.config.probe_timeout,
+        )
+        .await
     }
 
     async fn run_tcp_relay_server(&self) -> Result<()> {
@@ -316,6 +531,7 @@ impl ProxyClient {
 
                 trace!(dest_host = ?host, "new relay connection");
 
+                let dns_start = Instant::now();
                 let sock_addr = match self.dns_client.lookup_address(&host).await {
                     Ok(a) => a,
                     Err(e) => {
@@ -324,27 +540,84 @@ impl ProxyClient {
                     }
                 };
 
-                trace!(ip = ?ip, host = ?host, "lookup host");
+                let dns_latency = dns_start.elapsed();
+                trace!(ip = ?ip, host = ?host, dns_latency = %dns_latency.as_millis(), "lookup host");
 
-                match self
-                    .choose_proxy_tcp_stream(real_src, sock_addr, &host)
+                let (mut action, forced_direct) = match self
+                    .get_action_for_addr(real_src, SocketProtocol::Tcp, sock_addr, &host)
                     .await
                 {
+                    Ok(r) => r,
+                    Err(e) => {
+                        error!(?e, ?host, "error get action");
+                        return;
+                    }
+                };
+                trace!(?action, "selected action");
+
+                let connect_start = Instant::now();
+                let mut ret = self
+                    .choose_proxy_tcp_stream(action, real_src, sock_addr, &host)
+                    .await;
+                let refused =
+                    matches!(&ret, Err(e) if e.kind() == io::ErrorKind::ConnectionRefused);
+                // Some ISPs block an IP by refusing connections to it
+                if should_retry_with_proxy(
+                    refused,
+                    action,
+                    forced_direct,
+                    self.config.retry_on_refuse,
+                ) {
+                    debug!(?host, "Direct connection refused, retry with proxy");
+                    self.stats.record_refused_retry();
+                    action = Action::Proxy;
+                    ret = self
+                        .choose_proxy_tcp_stream(action, real_src, sock_addr, &host)
+                        .await;
+                }
+                match ret {
                     Ok(remote_conn) => {
-                        trace!("connect successfully");
-                        spawn(async move { tunnel_tcp_stream(conn, remote_conn).await });
+                        trace!(
+                            dns_latency = %dns_latency.as_millis(),
+                            connect_latency = %connect_start.elapsed().as_millis(),
+                            "connect successfully"
+                        );
+                        let guard = self.stats.open(action);
+                        let trace_first_bytes = self.config.trace_first_bytes.unwrap_or(0);
+                        let conn_log = self
+                            .tcp_logger
+                            .as_ref()
+                            .map(|logger| logger.open(real_src, &host, action));
+                        spawn(
+                            async move {
+                                let ret = tunnel_tcp_stream(
+                                    conn,
+                                    remote_conn,
+                                    trace_first_bytes,
+                                    conn_log,
+                                )
+                                .await;
+                                trace!(
+                                    duration_ms = guard.elapsed().as_millis() as u64,
+                                    "close connection"
+                                );
+                                drop(guard);
+                                ret
+                            }
+                            .instrument(Span::current()),
+                        );
                     }
                     Err(e) => {
-                        error!(?e, "connect error");
+                        error!(
+                            ?e,
+                            dns_latency = %dns_latency.as_millis(),
+                            connect_latency = %connect_start.elapsed().as_millis(),
+                            "connect error"
+                        );
                     }
                 };
             }
-            .instrument(trace_span!(
-                "tcp connection",
-                ?peer_addr,
-                ?real_src,
-                ?real_dest
-            ))
+            .instrument(tcp_connection_span(peer_addr, real_src, real_dest))
             .await
         }
         Ok::<(), io::Error>(())
@@ -353,18 +626,39 @@ impl ProxyClient {
     pub async fn run(&self) {
         self.run_tcp_relay_server()
             .race(self.run_udp_relay_server())
+            .race(self.print_stats_forever())
             .await
             .unwrap();
     }
 
-    fn get_udp_socket_and_dest_addr(&self, port: u16) -> Option<(ProxyUdpSocket, SocketAddr)> {
+    /// Number of TCP connections currently being relayed.
+    pub fn active_connection_count(&self) -> usize {
+        self.stats.active_connection_count()
+    }
+
+    async fn print_stats_forever(&self) -> Result<()> {
+        loop {
+            sleep(STATS_INTERVAL).await;
+            debug!(
+                active_connections = self.active_connection_count(),
+                per_action = %self.stats.action_summary(),
+                refused_retries = self.stats.refused_retry_count(),
+                durations = %self.stats.duration_summary(),
+                "Connection stats"
+            );
+            // peaks are reported per interval
+            self.stats.reset_stats();
+        }
+    }
+
+    fn get_udp_socket_and_dest_addr(&self, port: u16) -> Option<UdpSession> {
         let (real_src, real_dest) = self.session_manager.get_by_port(port)?;
         trace!(?real_src, ?real_dest, "new udp relay packet");
 
         self.udp_manager.read().get(&port).cloned()
     }
 
-    async fn new_udp_socket(&self, port: u16) -> Result<(ProxyUdpSocket, SocketAddr)> {
+    async fn new_udp_socket(&self, port: u16) -> Result<UdpSession> {
         let (real_src, real_dest) = match self.session_manager.get_by_port(port) {
             Some(s) => s,
             None => return Err(io::ErrorKind::AddrNotAvailable.into()),
@@ -386,55 +680,75 @@ impl ProxyClient {
         let socket = self
             .choose_proxy_udp_socket(real_src, sock_addr, &host)
             .await?;
-        self.udp_manager
-            .write()
-            .insert(port, (socket.clone(), sock_addr));
-        Ok((socket, sock_addr))
+        let session = (socket, sock_addr, Arc::new(Mutex::new(Instant::now())));
+        self.udp_manager.write().insert(port, session.clone());
+        Ok(session)
     }
 
     async fn run_udp_relay_server(&self) -> Result<()> {
         let udp_listener = Arc::new(UdpSocket::bind("0.0.0.0:1300").await?);
-        let recv_timeout = self.config.read_timeout;
+        let session_timeout = self.config.udp_session_timeout;
         let write_timeout = self.config.write_timeout;
         let mut buf = vec![0; 2000];
         loop {
             let (size, peer_addr) = udp_listener.recv_from(&mut buf).await?;
             assert!(size < 2000);
-            let (socket, dest_addr) = match self.get_udp_socket_and_dest_addr(peer_addr.port()) {
+            let (socket, dest_addr, last_active) = match self
+                .get_udp_socket_and_dest_addr(peer_addr.port())
+            {
                 None => {
-                    let (socket, dest_addr) = match self.new_udp_socket(peer_addr.port()).await {
+                    let session = match self.new_udp_socket(peer_addr.port()).await {
                         Ok(r) => r,
                         Err(e) => {
                             error!(?e, "new udp socket");
                             continue;
                         }
                     };
-                    let socket_clone = socket.clone();
+                    let (socket_clone, _, last_active_clone) = session.clone();
                     let udp_listener_clone = udp_listener.clone();
 
                     let udp_manager = self.udp_manager.clone();
-                    spawn(async move {
-                        let _: Result<()> = async {
-                            let mut buf = vec![0; 2000];
-                            loop {
-                                let (recv_size, _peer) =
-                                    timeout(recv_timeout, socket_clone.recv_from(&mut buf)).await?;
-                                assert!(recv_size < 2000);
-                                let send_size = timeout(
-                                    write_timeout,
-                                    udp_listener_clone.send_to(&buf[..size], peer_addr),
-                                )
-                                .await?;
-                                assert_eq!(send_size, size);
+                    spawn(
+                        async move {
+                            let _: Result<()> = async {
+                                let mut buf = vec![0; 2000];
+                                loop {
+                                    let ret =
+                                        timeout(session_timeout, socket_clone.recv_from(&mut buf))
+                                            .await;
+                                    let (recv_size, _peer) = match ret {
+                                        Ok(r) => r,
+                                        Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                                            // the client may still be sending
+                                            if last_active_clone.lock().elapsed() < session_timeout
+                                            {
+                                                continue;
+                                            }
+                                            debug!(?peer_addr, "UDP session expired");
+                                            return Ok(());
+                                        }
+                                        Err(e) => return Err(e),
+                                    };
+                                    assert!(recv_size < 2000);
+                                    *last_active_clone.lock() = Instant::now();
+                                    let send_size = timeout(
+                                        write_timeout,
+                                        udp_listener_clone.send_to(&buf[..recv_size], peer_addr),
+                                    )
+                                    .await?;
+                                    assert_eq!(send_size, recv_size);
+                                }
                             }
+                            .await;
+                            let _ = udp_manager.write().remove(&peer_addr.port());
                         }
-                        .await;
-                        let _ = udp_manager.write().remove(&peer_addr.port());
-                    });
-                    (socket, dest_addr)
+                        .instrument(Span::current()),
+                    );
+                    session
                 }
                 Some(r) => r,
             };
+            *last_active.lock() = Instant::now();
             match timeout(write_timeout, socket.send_to(&buf[..size], dest_addr)).await {
                 Ok(send_size) => {
                     assert_eq!(size, send_size);
@@ -445,35 +759,108 @@ impl ProxyClient {
     }
 }
 
+/// Relay data between `conn1` (the client) and `conn2` (the server). The first
+/// `trace_first_bytes` bytes of each direction are hex dumped in trace events, and all data
+/// is written to `conn_log` if set.
 async fn tunnel_tcp_stream<T1: Read + Write + Unpin + Clone, T2: Read + Write + Unpin + Clone>(
     mut conn1: T1,
     mut conn2: T2,
+    trace_first_bytes: usize,
+    conn_log: Option<ConnectionLog>,
 ) -> Result<()> {
     let mut conn1_clone = conn1.clone();
     let mut conn2_clone = conn2.clone();
     let f1 = async {
         let mut buf = vec![0; 1500];
+        let mut offset = 0;
         loop {
             let size = conn1.read(&mut buf).await?;
             if size == 0 {
                 break Ok(());
             }
+            trace_bytes("C→S", &mut offset, &buf[..size], trace_first_bytes);
+            if let Some(log) = &conn_log {
+                log.log("C→S", &buf[..size]);
+            }
             conn2.write_all(&buf[..size]).await?;
         }
     };
     let f2 = async {
         let mut buf = vec![0; 1500];
+        let mut offset = 0;
         loop {
             let size = conn2_clone.read(&mut buf).await?;
             if size == 0 {
                 break Ok(());
             }
+            trace_bytes("S→C", &mut offset, &buf[..size], trace_first_bytes);
+            if let Some(log) = &conn_log {
+                log.log("S→C", &buf[..size]);
+            }
             conn1_clone.write_all(&buf[..size]).await?;
         }
     };
     f1.race(f2).await
 }
 
+fn trace_bytes(direction: &str, offset: &mut usize, data: &[u8], limit: usize) {
+    if *offset >= limit {
+        return;
+    }
+    let len = data.len().min(limit - *offset);
+    trace!(direction, offset = *offset, bytes = %HexDump(&data[..len]), "first bytes");
+    *offset += data.len();
+}
+
+/// Whether a direct connection refused by the remote should be retried through the proxy.
+/// Connections forced direct, by the uid filter or because they go to a proxy server, must
+/// never be proxied.
+fn should_retry_with_proxy(
+    refused: bool,
+    action: Action,
+    forced_direct: bool,
+    retry_on_refuse: bool,
+) -> bool {
+    refused && action == Action::Direct && !forced_direct && retry_on_refuse
+}
+
+/// Span of a relayed TCP connection, the owner of its source socket is recorded in it once
+/// known.
+fn tcp_connection_span(peer_addr: SocketAddr, real_src: SocketAddr, real_dest: SocketAddr) -> Span {
+    trace_span!(
+        "tcp connection",
+        ?peer_addr,
+        ?real_src,
+        ?real_dest,
+        pid = field::Empty,
+        uid = field::Empty
+    )
+}
+
+/// Record the process and the user owning the source socket in the current span.
+fn record_socket_owner(pid: i32, uid: u32) {
+    let span = Span::current();
+    span.record("pid", &pid);
+    span.record("uid", &uid);
+}
+
+/// Start the SIP003 plugins of the shadowsocks servers, and point TCP connections of each
+/// server to its plugin.
+async fn start_plugins(config: &mut Config) -> Result<Vec<SsPlugin>> {
+    let mut plugins = vec![];
+    if let Some(servers) = &config.shadowsocks_servers {
+        let mut servers = servers.as_ref().clone();
+        for server in servers.iter_mut() {
+            if let Some(plugin) = SsPlugin::start(server).await? {
+                server.set_plugin_addr(Address::SocketAddress(plugin.local_addr()));
+                plugins.push(plugin);
+            }
+        }
+        config.shadowsocks_servers = Some(Arc::new(servers));
+    }
+    Ok(plugins)
+}
+
 async fn run_dns_resolver(config: &Config, resolver: AsyncStdResolver) -> RuleBasedDnsResolver {
     let (dns_server, resolver) = create_dns_server(
         "dns.db",
@@ -483,7 +870,7 @@ async fn run_dns_resolver(config: &Config, resolver: AsyncStdResolver) -> RuleBa
         resolver,
     )
     .await;
-    println!("Spawn DNS server");
+    info!("Spawn DNS server");
     spawn(
         dns_server
             .run_server()
@@ -492,16 +879,60 @@ async fn run_dns_resolver(config: &Config, resolver: AsyncStdResolver) -> RuleBa
     resolver
 }
 
-#[cfg(target_arch = "x86_64")]
-fn socket_addr_belong_to_user(addr: SocketAddr, uid: u32) -> Result<bool> {
-    use sysconfig::SocketInfo;
-    let user_socks: HashMap<i32, Vec<SocketInfo>> = sysconfig::list_user_proc_socks(uid)?;
-    Ok(user_socks
-        .values()
-        .any(|sockets| sockets.iter().any(|s| s.local == addr)))
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Debug;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::Registry;
+
+    /// Collects the fields recorded in spans.
+    #[derive(Clone, Default)]
+    struct SpanFields(Arc<Mutex<HashMap<String, String>>>);
+
+    impl Visit for SpanFields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .lock()
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for SpanFields {
+        fn new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
 
-#[cfg(not(target_arch = "x86_64"))]
-fn socket_addr_belong_to_user(_addr: SocketAddr, _uid: u32) -> Result<bool> {
-    Ok(true)
+        fn on_record(&self, _id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    #[test]
+    fn test_should_retry_with_proxy() {
+        assert!(should_retry_with_proxy(true, Action::Direct, false, true));
+        // passed by the uid filter
+        assert!(!should_retry_with_proxy(true, Action::Direct, true, true));
+        assert!(!should_retry_with_proxy(true, Action::Direct, false, false));
+        assert!(!should_retry_with_proxy(false, Action::Direct, false, true));
+        assert!(!should_retry_with_proxy(true, Action::Proxy, false, true));
+    }
+
+    #[test]
+    fn test_record_socket_owner() {
+        let fields = SpanFields::default();
+        let subscriber = Registry::default().with(fields.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let addr: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+            let span = tcp_connection_span(addr, addr, addr);
+            let _enter = span.enter();
+            record_socket_owner(42, 1000);
+        });
+        let fields = fields.0.lock();
+        assert_eq!(fields.get("pid").map(String::as_str), Some("42"));
+        assert_eq!(fields.get("uid").map(String::as_str), Some("1000"));
+    }
 }
@@ -0,0 +1,60 @@
+use parking_lot::Mutex;
+use std::time::Instant;
+
+/// Token bucket allowing `rate` acquisitions per second, with bursts up to `rate`. A `rate`
+/// of 0 means unlimited.
+pub struct RateLimiter {
+    rate: f64,
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32) -> Self {
+        let rate = f64::from(rate);
+        RateLimiter {
+            rate,
+            bucket: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    /// Take a token if there is one left.
+    pub fn try_acquire(&self) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+        let mut bucket = self.bucket.lock();
+        let (tokens, updated_at) = &mut *bucket;
+        let now = Instant::now();
+        *tokens =
+            (*tokens + now.duration_since(*updated_at).as_secs_f64() * self.rate).min(self.rate);
+        *updated_at = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_try_acquire() {
+        let limiter = RateLimiter::new(10);
+        assert!((0..10).all(|_| limiter.try_acquire()));
+        assert!(!limiter.try_acquire());
+        sleep(Duration::from_millis(150));
+        assert!(limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limiter = RateLimiter::new(0);
+        assert!((0..1000).all(|_| limiter.try_acquire()));
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Context, Result};
+use config::rule::ProxyRules;
+use config::Config;
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Replace the rules of `config` with the rules at `rule_url` if it is set. The rules in the
+/// config are kept if the remote rules can neither be fetched nor read from the cache.
+pub fn apply_remote_rules(config: &mut Config) {
+    if let Some(url) = &config.rule_url {
+        match load_rules_from_url(url, &config.rule_cache_path) {
+            Ok(rules) => config.rules = rules,
+            Err(e) => warn!(?e, "Load remote rules error, use rules in config"),
+        }
+    }
+}
+
+/// Fetch and parse the rule file at `url`, keeping a copy at `cache_path`. The cached copy is
+/// used when the rules can not be fetched.
+///
+/// Must be called before seeker takes over the system DNS, the request is sent by the host
+/// network directly.
+fn load_rules_from_url(url: &str, cache_path: &Path) -> Result<ProxyRules> {
+    match fetch_rules(url) {
+        Ok((rules, body)) => {
+            info!(url, count = rules.rules().len(), "Load rules from url");
+            if let Err(e) = fs::write(cache_path, body) {
+                warn!(%e, ?cache_path, "Write rule cache error");
+            }
+            return Ok(rules);
+        }
+        Err(e) => warn!(?e, url, "Load rules from url error, use cached rules"),
+    }
+    let body = fs::read_to_string(cache_path).context("Read rule cache error")?;
+    ProxyRules::from_rule_file(&body).context("Parse cached rules error")
+}
+
+fn fetch_rules(url: &str) -> Result<(ProxyRules, String)> {
+    let resp = ureq::get(url)
+        .timeout_read(5000)
+        .timeout_connect(5000)
+        .call();
+    if !resp.ok() {
+        return Err(anyhow!("status {}", resp.status()));
+    }
+    let body = resp.into_string()?;
+    let rules = ProxyRules::from_rule_file(&body)?;
+    Ok((rules, body))
+}
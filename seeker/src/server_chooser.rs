@@ -1,3 +1,4 @@
+use crate::bind::connect_tcp;
 use crate::dns_client::DnsClient;
 use async_std::io::timeout;
 use async_std::prelude::*;
@@ -5,18 +6,22 @@ use async_std::task::{sleep, spawn};
 use config::{Address, ShadowsocksServerConfig};
 use futures_util::stream::FuturesUnordered;
 use parking_lot::Mutex;
-use ssclient::SSTcpStream;
+use ssclient::{proxy_protocol_v2_header, SSTcpStream};
 use std::collections::HashMap;
 use std::io::Result;
+use std::net::IpAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tracing::{error, info};
+use tracing::{error, info, Span};
+use tracing_futures::Instrument;
 
 #[derive(Clone)]
 pub struct ShadowsocksServerChooser {
     ping_url: Vec<(Address, String)>,
     ping_timeout: Duration,
+    ping_interval: Duration,
+    bind_addr: Option<IpAddr>,
     servers: Arc<Vec<ShadowsocksServerConfig>>,
     candidates: Arc<Mutex<Vec<ShadowsocksServerConfig>>>,
     dns_client: DnsClient,
@@ -29,10 +34,14 @@ impl ShadowsocksServerChooser {
         dns_client: DnsClient,
         ping_url: Vec<(Address, String)>,
         ping_timeout: Duration,
+        ping_interval: Duration,
+        bind_addr: Option<IpAddr>,
     ) -> Self {
         let chooser = ShadowsocksServerChooser {
             ping_url,
             ping_timeout,
+            ping_interval,
+            bind_addr,
             candidates: Arc::new(Mutex::new(vec![])),
             servers,
             dns_client,
@@ -98,7 +107,7 @@ impl ShadowsocksServerChooser {
     pub async fn ping_servers_forever(&self) -> Result<()> {
         loop {
             self.ping_servers().await;
-            sleep(Duration::from_secs(30)).await;
+            sleep(self.ping_interval).await;
         }
     }
 
@@ -116,13 +125,16 @@ impl ShadowsocksServerChooser {
             .map(|config| {
                 let self_clone = self.clone();
                 let config_clone = config.clone();
-                spawn(async move {
-                    let duration = self_clone
-                        .ping_server(config_clone.clone())
-                        .await
-                        .map_err(|_| config_clone.clone())?;
-                    Ok::<_, ShadowsocksServerConfig>((config_clone, duration))
-                })
+                spawn(
+                    async move {
+                        let duration = self_clone
+                            .ping_server(config_clone.clone())
+                            .await
+                            .map_err(|_| config_clone.clone())?;
+                        Ok::<_, ShadowsocksServerConfig>((config_clone, duration))
+                    }
+                    .instrument(Span::current()),
+                )
             })
             .collect();
         while let Some(ret) = fut.next().await {
@@ -145,8 +157,19 @@ impl ShadowsocksServerChooser {
                 }
             }
         }
+        let mut current = self.candidates.lock();
         if !candidates.is_empty() {
-            *self.candidates.lock() = candidates;
+            *current = candidates;
+        } else if current.is_empty() {
+            // Connections need a candidate even if no server answered the first pings.
+            if let Some(first) = self.servers.first() {
+                error!(
+                    name = first.name(),
+                    server = ?first.addr(),
+                    "No shadowsocks server answered ping, use the first server"
+                );
+                current.push(first.clone());
+            }
         }
     }
 
@@ -154,13 +177,21 @@ impl ShadowsocksServerChooser {
         let instant = Instant::now();
         for (host, path) in &self.ping_url {
             let ret: Result<_> = timeout(self.ping_timeout, async {
-                let resolved_addr = self.dns_client.lookup_address(config.addr()).await?;
-                let mut conn = SSTcpStream::connect(
+                let resolved_addr = self.dns_client.lookup_address(config.tcp_addr()).await?;
+                // pings do not relay a client, let the receiver use the real endpoints
+                let proxy_header = if config.proxy_protocol() {
+                    Some(proxy_protocol_v2_header(None, resolved_addr))
+                } else {
+                    None
+                };
+                let stream = connect_tcp(self.bind_addr, resolved_addr, self.ping_timeout).await?;
+                let mut conn = SSTcpStream::connect_stream(
+                    stream,
                     host.clone(),
-                    resolved_addr,
                     Arc::new(AtomicBool::new(true)),
                     config.method(),
                     config.key(),
+                    proxy_header,
                 )
                 .await?;
                 conn.write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes())
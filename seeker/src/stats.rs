@@ -0,0 +1,175 @@
+use config::rule::Action;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const ACTIONS: [Action; 4] = [Action::Direct, Action::Proxy, Action::Reject, Action::Probe];
+/// Upper bounds in milliseconds and labels of the connection duration buckets
+const DURATION_BUCKETS: [(u128, &str); 6] = [
+    (10, "0-10ms"),
+    (100, "10-100ms"),
+    (1_000, "100ms-1s"),
+    (10_000, "1-10s"),
+    (60_000, "10-60s"),
+    (u128::MAX, "60s+"),
+];
+
+#[derive(Default)]
+struct ActionCounter {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+/// Number of closed connections in each of `DURATION_BUCKETS`, since start.
+#[derive(Default)]
+struct ConnectionDurationHistogram {
+    buckets: [AtomicUsize; 6],
+}
+
+impl ConnectionDurationHistogram {
+    fn record(&self, duration: Duration) {
+        self.buckets[bucket_index(duration)].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn summary(&self) -> String {
+        DURATION_BUCKETS
+            .iter()
+            .zip(self.buckets.iter())
+            .map(|((_, label), count)| format!("{}: {}", label, count.load(Ordering::SeqCst)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn bucket_index(duration: Duration) -> usize {
+    let ms = duration.as_millis();
+    DURATION_BUCKETS
+        .iter()
+        .position(|(upper, _)| ms < *upper)
+        .unwrap_or(DURATION_BUCKETS.len() - 1)
+}
+
+/// Counters for relayed TCP connections, shared between the relay server and the
+/// tunnel tasks it spawns.
+#[derive(Clone)]
+pub struct ConnectionStats {
+    active: Arc<AtomicUsize>,
+    refused_retries: Arc<AtomicUsize>,
+    durations: Arc<ConnectionDurationHistogram>,
+    per_action: Arc<HashMap<Action, ActionCounter>>,
+}
+
+impl Default for ConnectionStats {
+    fn default() -> Self {
+        ConnectionStats {
+            active: Arc::new(AtomicUsize::new(0)),
+            refused_retries: Arc::new(AtomicUsize::new(0)),
+            durations: Arc::new(ConnectionDurationHistogram::default()),
+            per_action: Arc::new(
+                ACTIONS
+                    .iter()
+                    .map(|action| (*action, ActionCounter::default()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl ConnectionStats {
+    /// Record a new connection routed with `action`. The connection is counted as active
+    /// until the returned guard is dropped.
+    pub fn open(&self, action: Action) -> ConnectionGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        let counter = &self.per_action[&action];
+        let current = counter.current.fetch_add(1, Ordering::SeqCst) + 1;
+        counter.peak.fetch_max(current, Ordering::SeqCst);
+        ConnectionGuard {
+            stats: self.clone(),
+            action,
+            opened_at: Instant::now(),
+        }
+    }
+
+    pub fn active_connection_count(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Record a direct connection refused by the remote and retried through the proxy.
+    pub fn record_refused_retry(&self) {
+        self.refused_retries.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Number of refused direct connections retried through the proxy since start.
+    pub fn refused_retry_count(&self) -> usize {
+        self.refused_retries.load(Ordering::SeqCst)
+    }
+
+    /// Reset the peak of every action to the number of connections currently open.
+    pub fn reset_stats(&self) {
+        for counter in self.per_action.values() {
+            counter
+                .peak
+                .store(counter.current.load(Ordering::SeqCst), Ordering::SeqCst);
+        }
+    }
+
+    /// Current and peak connections of every action, eg. `DIRECT: 2 (peak 5), PROXY: 0 (peak 1)`.
+    pub fn action_summary(&self) -> String {
+        ACTIONS
+            .iter()
+            .map(|action| {
+                let counter = &self.per_action[action];
+                format!(
+                    "{}: {} (peak {})",
+                    action,
+                    counter.current.load(Ordering::SeqCst),
+                    counter.peak.load(Ordering::SeqCst)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Closed connections per duration bucket, eg. `0-10ms: 3, 10-100ms: 12, ...`.
+    pub fn duration_summary(&self) -> String {
+        self.durations.summary()
+    }
+}
+
+pub struct ConnectionGuard {
+    stats: ConnectionStats,
+    action: Action,
+    opened_at: Instant,
+}
+
+impl ConnectionGuard {
+    /// Time since the connection was opened.
+    pub fn elapsed(&self) -> Duration {
+        self.opened_at.elapsed()
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.stats.durations.record(self.elapsed());
+        self.stats.active.fetch_sub(1, Ordering::SeqCst);
+        self.stats.per_action[&self.action]
+            .current
+            .fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_index() {
+        assert_eq!(bucket_index(Duration::from_millis(0)), 0);
+        assert_eq!(bucket_index(Duration::from_millis(10)), 1);
+        assert_eq!(bucket_index(Duration::from_millis(999)), 2);
+        assert_eq!(bucket_index(Duration::from_secs(59)), 4);
+        assert_eq!(bucket_index(Duration::from_secs(3600)), 5);
+    }
+}
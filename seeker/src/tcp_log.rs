@@ -0,0 +1,185 @@
+use config::rule::Action;
+use config::Address;
+use file_rotate::{FileRotate, RotationMode};
+use parking_lot::Mutex;
+use std::fmt;
+use std::io::{BufWriter, Result, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+const ROTATE_BYTES: usize = 100 * 1024 * 1024;
+const ROTATE_FILES: usize = 5;
+const BYTES_PER_LINE: usize = 16;
+
+/// Hex dump of all data relayed through TCP connections, for debugging application
+/// protocols. The file is rotated every 100 MB.
+///
+/// Records are written by a dedicated thread, so that relaying never waits for the disk.
+/// The channel to the thread is unbounded, records queue up in memory if the disk is slower
+/// than the relayed traffic.
+#[derive(Clone)]
+pub struct TcpConnectionLogger {
+    sender: Arc<Mutex<Sender<Message>>>,
+    next_conn_id: Arc<AtomicU64>,
+}
+
+enum Message {
+    Record(String),
+    Flush,
+}
+
+impl TcpConnectionLogger {
+    pub fn new(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = FileRotate::new(path, RotationMode::Bytes(ROTATE_BYTES), ROTATE_FILES);
+        let (sender, receiver) = channel();
+        thread::Builder::new()
+            .name("debug-tcp-log".to_string())
+            .spawn(move || write_messages(BufWriter::new(file), receiver))?;
+        Ok(TcpConnectionLogger {
+            sender: Arc::new(Mutex::new(sender)),
+            next_conn_id: Arc::new(AtomicU64::new(1)),
+        })
+    }
+
+    /// Log a new connection and return the log of its data.
+    pub fn open(&self, src: SocketAddr, dest: &Address, action: Action) -> ConnectionLog {
+        let conn_id = self.next_conn_id.fetch_add(1, Ordering::SeqCst);
+        self.write_record(format!(
+            "{} conn={} open {} -> {} {}\n",
+            timestamp(),
+            conn_id,
+            src,
+            dest,
+            action
+        ));
+        ConnectionLog {
+            logger: self.clone(),
+            conn_id,
+        }
+    }
+
+    /// Send a whole record at once, so that records of concurrent connections don't
+    /// interleave. Records are buffered, they reach the file when the buffer is full or a
+    /// connection is closed.
+    fn write_record(&self, record: String) {
+        self.send(Message::Record(record));
+    }
+
+    fn flush(&self) {
+        self.send(Message::Flush);
+    }
+
+    fn send(&self, message: Message) {
+        // Only fails if the writer thread is gone, its errors are logged there.
+        let _ = self.sender.lock().send(message);
+    }
+}
+
+/// Write the messages of `receiver` until all senders are dropped.
+fn write_messages(mut file: BufWriter<FileRotate>, receiver: Receiver<Message>) {
+    for message in receiver {
+        let ret = match message {
+            Message::Record(record) => file.write_all(record.as_bytes()),
+            Message::Flush => file.flush(),
+        };
+        if let Err(e) = ret {
+            error!(?e, "write debug tcp log");
+        }
+    }
+    let _ = file.flush();
+}
+
+/// Log of the data of one connection.
+pub struct ConnectionLog {
+    logger: TcpConnectionLogger,
+    conn_id: u64,
+}
+
+impl ConnectionLog {
+    pub fn log(&self, direction: &str, data: &[u8]) {
+        self.logger.write_record(format!(
+            "{} conn={} {} {} bytes\n{:#}",
+            timestamp(),
+            self.conn_id,
+            direction,
+            data.len(),
+            HexDump(data)
+        ));
+    }
+}
+
+impl Drop for ConnectionLog {
+    fn drop(&mut self) {
+        self.logger.flush();
+    }
+}
+
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}.{:03}", now.as_secs(), now.subsec_millis())
+}
+
+/// Formats bytes as space separated hex, only when the value is formatted, so that disabled
+/// trace events cost nothing. The alternate form `{:#}` is `hexdump -C` like: offset, 16 hex
+/// bytes and their printable characters per line.
+pub struct HexDump<'a>(pub &'a [u8]);
+
+impl fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !f.alternate() {
+            for (i, b) in self.0.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{:02x}", b)?;
+            }
+            return Ok(());
+        }
+        for (i, chunk) in self.0.chunks(BYTES_PER_LINE).enumerate() {
+            write!(f, "{:08x} ", i * BYTES_PER_LINE)?;
+            for j in 0..BYTES_PER_LINE {
+                match chunk.get(j) {
+                    Some(b) => write!(f, " {:02x}", b)?,
+                    None => write!(f, "   ")?,
+                }
+            }
+            write!(f, "  |")?;
+            for b in chunk {
+                let c = if b.is_ascii_graphic() || *b == b' ' {
+                    *b as char
+                } else {
+                    '.'
+                };
+                write!(f, "{}", c)?;
+            }
+            writeln!(f, "|")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump() {
+        assert_eq!(HexDump(b"\x01\xab ").to_string(), "01 ab 20");
+        assert_eq!(
+            format!("{:#}", HexDump(b"EHLO example.com\r\nQUIT")),
+            "00000000  45 48 4c 4f 20 65 78 61 6d 70 6c 65 2e 63 6f 6d  |EHLO example.com|\n\
+             00000010  0d 0a 51 55 49 54                                |..QUIT|\n"
+        );
+    }
+}
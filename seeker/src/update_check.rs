@@ -0,0 +1,66 @@
+use async_std::task::{sleep, spawn_blocking};
+use std::time::Duration;
+use tracing::{debug, info};
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/reidentify/seeker/releases/latest";
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Check for a new release once a day, logging when one is available.
+///
+/// The request runs on a blocking thread and its errors are only logged at debug level, the
+/// check never affects relaying.
+pub async fn check_for_updates_forever() {
+    let current = env!("CARGO_PKG_VERSION");
+    loop {
+        match spawn_blocking(latest_release_tag).await {
+            Ok(latest) if is_newer(&latest, current) => {
+                info!(%latest, %current, "New seeker release available");
+            }
+            Ok(latest) => debug!(%latest, %current, "Seeker is up to date"),
+            Err(e) => debug!(%e, "Check for updates error"),
+        }
+        sleep(CHECK_INTERVAL).await;
+    }
+}
+
+fn latest_release_tag() -> Result<String, String> {
+    let resp = ureq::get(LATEST_RELEASE_URL)
+        .set("User-Agent", concat!("seeker/", env!("CARGO_PKG_VERSION")))
+        .timeout_connect(5000)
+        .timeout_read(5000)
+        .call();
+    if !resp.ok() {
+        return Err(format!("status {}", resp.status()));
+    }
+    let release: serde_json::Value =
+        serde_json::from_reader(resp.into_reader()).map_err(|e| e.to_string())?;
+    release["tag_name"]
+        .as_str()
+        .map(ToString::to_string)
+        .ok_or_else(|| "no tag_name in response".to_string())
+}
+
+/// Compare dotted numeric versions, ignoring a leading `v`. Unparsable parts count as 0.
+fn is_newer(latest: &str, current: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> {
+        v.trim_start_matches('v')
+            .split('.')
+            .map(|n| n.parse().unwrap_or(0))
+            .collect()
+    };
+    parse(latest) > parse(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("v0.3.0", "0.2.0"));
+        assert!(is_newer("0.2.10", "0.2.9"));
+        assert!(is_newer("v1.0", "0.9.9"));
+        assert!(!is_newer("v0.2.0", "0.2.0"));
+        assert!(!is_newer("v0.1.9", "0.2.0"));
+    }
+}
@@ -3,5 +3,5 @@ mod types;
 mod udp;
 
 pub use tcp::Socks5TcpStream;
-pub use types::Address;
+pub use types::{Address, AddressParseError};
 pub use udp::Socks5UdpSocket;
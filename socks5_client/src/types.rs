@@ -318,6 +318,14 @@ impl Address {
     pub fn serialized_len(&self) -> usize {
         get_addr_len(self)
     }
+
+    /// Encodes in SOCKS5 address format (ATYP + ADDR + PORT), which is also the address
+    /// header of the shadowsocks protocol.
+    pub fn to_wire_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.serialized_len());
+        self.write_to_buf(&mut buf);
+        buf
+    }
 }
 
 impl Debug for Address {
@@ -364,30 +372,100 @@ impl From<(String, u16)> for Address {
 }
 
 /// Parse `Address` error
-#[derive(Debug)]
-pub struct AddressError;
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AddressParseError {
+    /// No host before the port, eg. `:80`
+    EmptyHostname,
+    /// Nothing after the port separator, eg. `example.com:`
+    MissingPort,
+    /// Port is not a number between 0 and 65535
+    InvalidPort(String),
+    /// Host looks like an IPv4 address but is not a valid one, eg. `1.2.3.256`
+    InvalidIpv4(String),
+    /// Host looks like an IPv6 address but is not a valid one, eg. `[::g]`
+    InvalidIpv6(String),
+}
+
+impl fmt::Display for AddressParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            AddressParseError::EmptyHostname => write!(f, "empty hostname"),
+            AddressParseError::MissingPort => write!(f, "missing port"),
+            AddressParseError::InvalidPort(p) => write!(f, "invalid port {}", p),
+            AddressParseError::InvalidIpv4(ip) => write!(f, "invalid IPv4 address {}", ip),
+            AddressParseError::InvalidIpv6(ip) => write!(f, "invalid IPv6 address {}", ip),
+        }
+    }
+}
+
+impl error::Error for AddressParseError {}
+
+/// Port used when the address has no port, http's default port.
+const DEFAULT_PORT: u16 = 80;
+
+fn parse_port(port: Option<&str>) -> Result<u16, AddressParseError> {
+    match port {
+        None => Ok(DEFAULT_PORT),
+        Some("") => Err(AddressParseError::MissingPort),
+        Some(p) => p
+            .parse::<u16>()
+            .map_err(|_| AddressParseError::InvalidPort(p.to_string())),
+    }
+}
 
 impl FromStr for Address {
-    type Err = AddressError;
-
-    fn from_str(s: &str) -> Result<Address, AddressError> {
-        match s.parse::<SocketAddr>() {
-            Ok(addr) => Ok(Address::SocketAddress(addr)),
-            Err(..) => {
-                let mut sp = s.split(':');
-                match (sp.next(), sp.next()) {
-                    (Some(dn), Some(port)) => match port.parse::<u16>() {
-                        Ok(port) => Ok(Address::DomainNameAddress(dn.to_owned(), port)),
-                        Err(..) => Err(AddressError),
-                    },
-                    (Some(dn), None) => {
-                        // Assume it is 80 (http's default port)
-                        Ok(Address::DomainNameAddress(dn.to_owned(), 80))
-                    }
-                    _ => Err(AddressError),
-                }
-            }
+    type Err = AddressParseError;
+
+    /// Parses `ip:port`, `[ipv6]:port` or `domain:port`. The port defaults to 80 when omitted.
+    fn from_str(s: &str) -> Result<Address, AddressParseError> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Address::SocketAddress(addr));
+        }
+        if let Ok(ip) = s.parse::<Ipv6Addr>() {
+            return Ok(Address::SocketAddress(SocketAddr::new(
+                ip.into(),
+                DEFAULT_PORT,
+            )));
+        }
+
+        if s.starts_with('[') {
+            let end = s
+                .find(']')
+                .ok_or_else(|| AddressParseError::InvalidIpv6(s.to_string()))?;
+            let ip = &s[1..end];
+            let ip = ip
+                .parse::<Ipv6Addr>()
+                .map_err(|_| AddressParseError::InvalidIpv6(ip.to_string()))?;
+            let port = match &s[end + 1..] {
+                "" => None,
+                rest if rest.starts_with(':') => Some(&rest[1..]),
+                rest => return Err(AddressParseError::InvalidPort(rest.to_string())),
+            };
+            return Ok(Address::SocketAddress(SocketAddr::new(
+                ip.into(),
+                parse_port(port)?,
+            )));
+        }
+
+        let (host, port) = match s.rfind(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        if host.contains(':') {
+            return Err(AddressParseError::InvalidIpv6(s.to_string()));
         }
+        if host.is_empty() {
+            return Err(AddressParseError::EmptyHostname);
+        }
+        let port = parse_port(port)?;
+
+        if host.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            let ip = host
+                .parse::<Ipv4Addr>()
+                .map_err(|_| AddressParseError::InvalidIpv4(host.to_string()))?;
+            return Ok(Address::SocketAddress(SocketAddr::new(ip.into(), port)));
+        }
+        Ok(Address::DomainNameAddress(host.to_owned(), port))
     }
 }
 
@@ -797,3 +875,90 @@ impl UdpAssociateHeader {
         3 + self.address.serialized_len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_display() {
+        let v4: Address = "127.0.0.1:443".parse::<SocketAddr>().unwrap().into();
+        assert_eq!(v4.to_string(), "127.0.0.1:443");
+        let v6: Address = "[::1]:443".parse::<SocketAddr>().unwrap().into();
+        assert_eq!(v6.to_string(), "[::1]:443");
+        let domain = Address::DomainNameAddress("example.com".to_string(), 80);
+        assert_eq!(domain.to_string(), "example.com:80");
+    }
+
+    #[test]
+    fn test_address_to_wire_bytes() {
+        let v4: Address = "127.0.0.1:443".parse::<SocketAddr>().unwrap().into();
+        assert_eq!(v4.to_wire_bytes(), vec![0x01, 127, 0, 0, 1, 0x01, 0xbb]);
+
+        let v6: Address = "[::1]:80".parse::<SocketAddr>().unwrap().into();
+        let mut expected = vec![0x04];
+        expected.extend_from_slice(&[0; 15]);
+        expected.extend_from_slice(&[1, 0x00, 0x50]);
+        assert_eq!(v6.to_wire_bytes(), expected);
+
+        let domain = Address::DomainNameAddress("a.io".to_string(), 80);
+        assert_eq!(
+            domain.to_wire_bytes(),
+            vec![0x03, 4, b'a', b'.', b'i', b'o', 0x00, 0x50]
+        );
+        assert_eq!(domain.to_wire_bytes().len(), domain.serialized_len());
+    }
+
+    #[test]
+    fn test_address_from_str_round_trip() {
+        for s in &[
+            "127.0.0.1:80",
+            "[::1]:443",
+            "[2001:db8::1]:8388",
+            "example.com:443",
+        ] {
+            assert_eq!(&s.parse::<Address>().unwrap().to_string(), s);
+        }
+        assert_eq!(
+            "::1".parse::<Address>().unwrap().to_string(),
+            "[::1]:80".to_string()
+        );
+        assert_eq!(
+            "example.com".parse::<Address>(),
+            Ok(Address::DomainNameAddress("example.com".to_string(), 80))
+        );
+        assert_eq!(
+            "10.0.0.1".parse::<Address>(),
+            Ok(Address::SocketAddress("10.0.0.1:80".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_address_from_str_error() {
+        use AddressParseError::*;
+
+        assert_eq!(":80".parse::<Address>(), Err(EmptyHostname));
+        assert_eq!("example.com:".parse::<Address>(), Err(MissingPort));
+        assert_eq!("[::1]:".parse::<Address>(), Err(MissingPort));
+        assert_eq!(
+            "example.com:http".parse::<Address>(),
+            Err(InvalidPort("http".to_string()))
+        );
+        assert_eq!(
+            "example.com:65536".parse::<Address>(),
+            Err(InvalidPort("65536".to_string()))
+        );
+        assert_eq!(
+            "1.2.3.256:80".parse::<Address>(),
+            Err(InvalidIpv4("1.2.3.256".to_string()))
+        );
+        assert_eq!(
+            "[::g]:80".parse::<Address>(),
+            Err(InvalidIpv6("::g".to_string()))
+        );
+        assert_eq!(
+            "fe80::1::2".parse::<Address>(),
+            Err(InvalidIpv6("fe80::1::2".to_string()))
+        );
+    }
+}
@@ -1,8 +1,10 @@
+mod proxy_protocol;
 mod tcp_io;
 mod udp_io;
 
 const BUFFER_SIZE: usize = 8 * 1024; // 8K buffer
 
+pub use proxy_protocol::proxy_protocol_v2_header;
 pub use tcp_io::SSTcpStream;
 pub use udp_io::crypto_io::{decrypt_payload, encrypt_payload};
 pub use udp_io::SSUdpSocket;
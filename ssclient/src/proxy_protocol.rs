@@ -0,0 +1,87 @@
+//! PROXY protocol v2 header, sent before any shadowsocks data to load balancers in front of
+//! the server.
+//!
+//! See https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt
+use std::net::{IpAddr, SocketAddr};
+
+const SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+const CMD_LOCAL: u8 = 0x20;
+const CMD_PROXY: u8 = 0x21;
+const AF_UNSPEC: u8 = 0x00;
+const TCP_OVER_IPV4: u8 = 0x11;
+const TCP_OVER_IPV6: u8 = 0x21;
+
+/// Build the header for a connection from `src` to `dst`.
+///
+/// Without `src`, eg. for health checks that are not relaying a client, a `LOCAL` header is
+/// built, which tells the receiver to use the real connection endpoints.
+pub fn proxy_protocol_v2_header(src: Option<SocketAddr>, dst: SocketAddr) -> Vec<u8> {
+    let mut header = SIGNATURE.to_vec();
+    let src = match src {
+        Some(src) => src,
+        None => {
+            header.extend_from_slice(&[CMD_LOCAL, AF_UNSPEC, 0, 0]);
+            return header;
+        }
+    };
+    header.push(CMD_PROXY);
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            header.push(TCP_OVER_IPV4);
+            header.extend_from_slice(&12u16.to_be_bytes());
+            header.extend_from_slice(&src_ip.octets());
+            header.extend_from_slice(&dst_ip.octets());
+        }
+        // both addresses must be of the same family, map the ipv4 one into ipv6
+        (src_ip, dst_ip) => {
+            header.push(TCP_OVER_IPV6);
+            header.extend_from_slice(&36u16.to_be_bytes());
+            header.extend_from_slice(&to_ipv6_octets(src_ip));
+            header.extend_from_slice(&to_ipv6_octets(dst_ip));
+        }
+    }
+    header.extend_from_slice(&src.port().to_be_bytes());
+    header.extend_from_slice(&dst.port().to_be_bytes());
+    header
+}
+
+fn to_ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxy_protocol_v2_header() {
+        let src = "10.0.0.1:50000".parse().unwrap();
+        let dst = "192.168.1.2:8388".parse().unwrap();
+        let header = proxy_protocol_v2_header(Some(src), dst);
+        assert_eq!(&header[..12], &SIGNATURE);
+        assert_eq!(
+            &header[12..],
+            &[0x21, 0x11, 0, 12, 10, 0, 0, 1, 192, 168, 1, 2, 0xC3, 0x50, 0x20, 0xC4]
+        );
+
+        let dst = "[::1]:8388".parse().unwrap();
+        let header = proxy_protocol_v2_header(Some(src), dst);
+        assert_eq!(&header[12..16], &[0x21, 0x21, 0, 36]);
+        assert_eq!(
+            &header[16..32],
+            &"::ffff:10.0.0.1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets()
+        );
+        assert_eq!(header.len(), 16 + 36);
+
+        let header = proxy_protocol_v2_header(None, dst);
+        assert_eq!(&header[12..], &[0x20, 0x00, 0, 0]);
+    }
+}
@@ -11,7 +11,7 @@ use std::{
     task::{Context, Poll},
 };
 
-use bytes::{Bytes, BytesMut};
+use bytes::Bytes;
 use futures_util::ready;
 use tracing::trace;
 
@@ -61,14 +61,33 @@ pub struct SSTcpStream {
 
 impl SSTcpStream {
     /// Create a new CryptoStream with the underlying stream connection
+    ///
+    /// `proxy_header` is written to the server before any encrypted data, see
+    /// `proxy_protocol_v2_header`.
     pub async fn connect(
         addr: Address,
         server_addr: SocketAddr,
         server_alive: Arc<AtomicBool>,
         method: CipherType,
         key: Bytes,
+        proxy_header: Option<Vec<u8>>,
     ) -> Result<SSTcpStream> {
         let stream = TcpStream::connect(server_addr).await?;
+        SSTcpStream::connect_stream(stream, addr, server_alive, method, key, proxy_header).await
+    }
+
+    /// Same as `connect`, over a `stream` already connected to the server
+    pub async fn connect_stream(
+        mut stream: TcpStream,
+        addr: Address,
+        server_alive: Arc<AtomicBool>,
+        method: CipherType,
+        key: Bytes,
+        proxy_header: Option<Vec<u8>>,
+    ) -> Result<SSTcpStream> {
+        if let Some(header) = proxy_header {
+            stream.write_all(&header).await?;
+        }
         let prev_len = match method.category() {
             CipherCategory::Stream => method.iv_size(),
             CipherCategory::Aead => method.salt_size(),
@@ -112,9 +131,7 @@ impl SSTcpStream {
             server_alive,
         };
 
-        let mut addr_buf = BytesMut::with_capacity(addr.serialized_len());
-        addr.write_to_buf(&mut addr_buf);
-        ss_stream.write_all(&addr_buf).await?;
+        ss_stream.write_all(&addr.to_wire_bytes()).await?;
         Ok(ss_stream)
     }
 
@@ -343,6 +360,7 @@ mod tests {
                 Arc::new(AtomicBool::new(true)),
                 method,
                 key_clone,
+                None,
             )
             .await
             .unwrap();
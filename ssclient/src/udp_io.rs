@@ -59,8 +59,7 @@ impl SSUdpSocket {
         );
 
         // CLIENT -> SERVER protocol: ADDRESS + PAYLOAD
-        let mut send_buf = Vec::with_capacity(addr.serialized_len() + payload.len());
-        addr.write_to_buf(&mut send_buf);
+        let mut send_buf = addr.to_wire_bytes();
         send_buf.extend_from_slice(payload);
 
         let mut encrypt_buf = BytesMut::with_capacity(MAXIMUM_UDP_PAYLOAD_SIZE);
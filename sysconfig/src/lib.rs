@@ -6,7 +6,14 @@ mod ulimit;
 
 pub use net::{setup_ip, DNSSetup, IpForward};
 #[cfg(target_arch = "x86_64")]
-pub use proc::sys::{list_system_proc_socks, list_user_proc_socks};
+pub use proc::sys::{list_system_proc_socks, list_user_proc_socks, process_owner};
 #[cfg(target_arch = "x86_64")]
 pub use proc::SocketInfo;
 pub use ulimit::{get_rlimit_no_file, set_rlimit_no_file};
+
+/// Transport protocol of a socket.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SocketProtocol {
+    Tcp,
+    Udp,
+}
@@ -1,11 +1,13 @@
 #![allow(dead_code)]
 use super::SocketInfo;
+use crate::SocketProtocol;
+use libproc::libproc::bsd_info::BSDInfo;
 use libproc::libproc::proc_pid::{
-    listpidinfo, listpids, pidfdinfo, InSockInfo, ListFDs, ProcFDType, ProcType, SocketFDInfo,
-    SocketInfoKind,
+    listpidinfo, listpids, pidfdinfo, pidinfo, InSockInfo, ListFDs, ProcFDType, ProcType,
+    SocketFDInfo, SocketInfoKind,
 };
 use std::collections::HashMap;
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 
 pub fn list_system_proc_socks() -> Result<HashMap<i32, Vec<SocketInfo>>> {
@@ -33,6 +35,12 @@ pub fn list_user_proc_socks(uid: u32) -> Result<HashMap<i32, Vec<SocketInfo>>> {
     Ok(pid_sockaddr_map)
 }
 
+/// Uid of the user running process `pid`.
+pub fn process_owner(pid: i32) -> Result<u32> {
+    let info = pidinfo::<BSDInfo>(pid, 0).map_err(|e| Error::new(ErrorKind::Other, e))?;
+    Ok(info.pbi_uid)
+}
+
 fn list_sockaddr(pid: i32) -> Result<Vec<SocketInfo>> {
     let mut addrs = vec![];
     for fd in listpidinfo::<ListFDs>(pid, 4000)? {
@@ -43,7 +51,11 @@ fn list_sockaddr(pid: i32) -> Result<Vec<SocketInfo>> {
                     let info = unsafe { socket.psi.soi_proto.pri_tcp };
                     let local = get_local_addr(info.tcpsi_ini, socket.psi.soi_family);
                     let remote = get_foreign_addr(info.tcpsi_ini, socket.psi.soi_family);
-                    addrs.push(SocketInfo { local, remote });
+                    addrs.push(SocketInfo {
+                        local,
+                        remote,
+                        protocol: SocketProtocol::Tcp,
+                    });
                 }
             }
         }
@@ -1,47 +1,30 @@
-use crate::SocketInfo;
-use procfs::process::FDTarget;
+use crate::{SocketInfo, SocketProtocol};
+use procfs::process::{FDTarget, Process};
 use std::collections::HashMap;
 use std::io::Result;
+use std::os::unix::fs::MetadataExt;
 
 pub fn list_system_proc_socks() -> Result<HashMap<i32, Vec<SocketInfo>>> {
     let all_procs = procfs::process::all_processes().expect("list all processes");
-
-    // build up a map between socket inodes and processes:
-    let mut map = HashMap::new();
-    for process in &all_procs {
-        if let Ok(fds) = process.fd() {
-            for fd in fds {
-                if let FDTarget::Socket(inode) = fd.target {
-                    map.insert(inode, process);
-                }
-            }
-        }
-    }
-
-    let mut socks_map = HashMap::new();
-    // get the tcp table
-    let tcp = procfs::net::tcp().unwrap();
-    let tcp6 = procfs::net::tcp6().unwrap();
-    for entry in tcp.into_iter().chain(tcp6) {
-        // find the process (if any) that has an open FD to this entry's inode
-        if let Some(process) = map.get(&entry.inode) {
-            let item = socks_map.entry(process.pid()).or_insert(vec![]);
-            item.push(SocketInfo {
-                local: entry.local_address,
-                remote: entry.remote_address,
-            });
-        } else {
-        }
-    }
-    Ok(socks_map)
+    list_proc_socks(all_procs.iter())
 }
 
 pub fn list_user_proc_socks(uid: u32) -> Result<HashMap<i32, Vec<SocketInfo>>> {
     let all_procs = procfs::process::all_processes().expect("list all processes");
+    list_proc_socks(all_procs.iter().filter(|p| p.owner == uid))
+}
+
+/// Uid of the user running process `pid`.
+pub fn process_owner(pid: i32) -> Result<u32> {
+    Ok(std::fs::metadata(format!("/proc/{}", pid))?.uid())
+}
 
+fn list_proc_socks<'a>(
+    procs: impl Iterator<Item = &'a Process>,
+) -> Result<HashMap<i32, Vec<SocketInfo>>> {
     // build up a map between socket inodes and processes:
     let mut map = HashMap::new();
-    for process in all_procs.iter().filter(|p| p.owner == uid) {
+    for process in procs {
         if let Ok(fds) = process.fd() {
             for fd in fds {
                 if let FDTarget::Socket(inode) = fd.target {
@@ -50,19 +33,42 @@ pub fn list_user_proc_socks(uid: u32) -> Result<HashMap<i32, Vec<SocketInfo>>> {
             }
         }
     }
+
     let mut socks_map = HashMap::new();
-    // get the tcp table
-    let tcp = procfs::net::tcp().unwrap();
-    let tcp6 = procfs::net::tcp6().unwrap();
-    for entry in tcp.into_iter().chain(tcp6) {
+    // get the tcp and udp tables, both ipv4 and ipv6
+    let tcp = procfs::net::tcp()
+        .unwrap()
+        .into_iter()
+        .chain(procfs::net::tcp6().unwrap())
+        .map(|e| {
+            (
+                e.local_address,
+                e.remote_address,
+                e.inode,
+                SocketProtocol::Tcp,
+            )
+        });
+    let udp = procfs::net::udp()
+        .unwrap()
+        .into_iter()
+        .chain(procfs::net::udp6().unwrap())
+        .map(|e| {
+            (
+                e.local_address,
+                e.remote_address,
+                e.inode,
+                SocketProtocol::Udp,
+            )
+        });
+    for (local, remote, inode, protocol) in tcp.chain(udp) {
         // find the process (if any) that has an open FD to this entry's inode
-        if let Some(process) = map.get(&entry.inode) {
-            let item = socks_map.entry(process.pid()).or_insert(vec![]);
+        if let Some(process) = map.get(&inode) {
+            let item = socks_map.entry(process.pid()).or_insert_with(Vec::new);
             item.push(SocketInfo {
-                local: entry.local_address,
-                remote: entry.remote_address,
+                local,
+                remote,
+                protocol,
             });
-        } else {
         }
     }
     Ok(socks_map)
@@ -82,4 +88,20 @@ mod test {
             .values()
             .any(|sockets| sockets.iter().any(|s| s.local.port() == 65532)));
     }
+
+    #[test]
+    fn test_process_owner() {
+        let uid = unsafe { libc::getuid() };
+        assert_eq!(process_owner(std::process::id() as i32).unwrap(), uid);
+    }
+
+    #[test]
+    fn test_list_user_proc_udp_socks() {
+        let uid = unsafe { libc::getuid() };
+        let _socket = std::net::UdpSocket::bind("[::1]:65531").unwrap();
+        let s = list_user_proc_socks(uid).unwrap();
+        assert!(s
+            .values()
+            .any(|sockets| sockets.iter().any(|s| s.local.port() == 65531)));
+    }
 }
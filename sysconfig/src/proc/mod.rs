@@ -1,9 +1,11 @@
+use crate::SocketProtocol;
 use std::net::SocketAddr;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct SocketInfo {
     pub local: SocketAddr,
     pub remote: SocketAddr,
+    pub protocol: SocketProtocol,
 }
 
 #[cfg(target_os = "macos")]
@@ -63,6 +63,13 @@ macro_rules! route_packet {
     }};
 }
 
+/// Create the tun device `tun_name` and close it right away, to check that seeker has the
+/// permission to do it. Returns the name of the created device.
+pub fn check_tun_device(tun_name: &str) -> Result<String> {
+    let tun = TunSocket::new(tun_name)?;
+    tun.name()
+}
+
 pub fn run_nat(
     tun_name: &str,
     tun_ip: Ipv4Addr,